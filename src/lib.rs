@@ -1,10 +1,22 @@
 // src/lib.rs
 pub mod logger {
-    use chrono::Local;
+    use crate::remote::{LokiWriter, RemoteWriter, TcpWriter};
+    #[cfg(feature = "syslog")]
+    use crate::syslog::SyslogWriter;
+    #[cfg(feature = "chrono")]
+    use chrono::{DateTime, Local, Utc};
+    use std::borrow::Cow;
+    use std::cell::{OnceCell, RefCell};
+    use std::collections::{BTreeMap, VecDeque};
     use std::env;
     use std::fs::OpenOptions;
     use std::io::{self, Write};
-    use std::fmt::Arguments;
+    use std::path::{Path, PathBuf};
+    use std::fmt::{self, Arguments, Display, Write as _};
+    use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex, OnceLock, RwLock};
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
     pub struct Colours;
 
@@ -13,195 +25,8176 @@ pub mod logger {
         pub const GREEN: &'static str = "\x1b[32m";
         pub const YELLOW: &'static str = "\x1b[33m";
         pub const BLUE: &'static str = "\x1b[34m";
+        pub const MAGENTA: &'static str = "\x1b[35m";
         pub const CYAN: &'static str = "\x1b[36m";
         pub const WHITE: &'static str = "\x1b[37m";
     }
 
-    #[derive(Debug, PartialEq)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
     pub enum LogLevel {
+        /// Above Error, for records that should page someone.
+        Critical,
         Error,
         Warning,
         Debug,
         Success,
         Info,
         Log,
+        /// A level added with `Logger::register_level`, by registration index. An index
+        /// nothing was registered at logs as "CUSTOM", ranked like Info.
+        Custom(u16),
+    }
+
+    /// A user-defined level: its name, rank on the `LogLevel::severity` scale and colour.
+    #[derive(Debug, Clone, Copy)]
+    struct CustomLevel {
+        name: &'static str,
+        severity: u8,
+        colour: &'static str,
     }
 
+    static CUSTOM_LEVELS: RwLock<Vec<CustomLevel>> = RwLock::new(Vec::new());
+
+    /// Stands in for a `LogLevel::Custom` index nothing was registered at, ranked like Info.
+    const UNREGISTERED_LEVEL: CustomLevel = CustomLevel {
+        name: "CUSTOM",
+        severity: 2,
+        colour: Colours::WHITE,
+    };
+
     impl LogLevel {
-        fn as_str(&self) -> &'static str {
+        fn custom(index: u16) -> CustomLevel {
+            CUSTOM_LEVELS
+                .read()
+                .unwrap()
+                .get(usize::from(index))
+                .copied()
+                .unwrap_or(UNREGISTERED_LEVEL)
+        }
+
+        pub(crate) fn as_str(&self) -> &'static str {
             match self {
+                LogLevel::Critical => "CRITICAL",
                 LogLevel::Error => "ERROR",
                 LogLevel::Warning => "WARNING",
                 LogLevel::Debug => "DEBUG",
                 LogLevel::Success => "SUCCESS",
                 LogLevel::Info => "INFO",
                 LogLevel::Log => "LOG",
+                LogLevel::Custom(index) => Self::custom(*index).name,
             }
         }
-    }
 
-    #[derive(Debug)]
-    pub struct Logger;
+        /// Inverse of `as_str`.
+        pub(crate) fn from_name(name: &str) -> Option<Self> {
+            match name {
+                "CRITICAL" => Some(LogLevel::Critical),
+                "ERROR" => Some(LogLevel::Error),
+                "WARNING" => Some(LogLevel::Warning),
+                "DEBUG" => Some(LogLevel::Debug),
+                "SUCCESS" => Some(LogLevel::Success),
+                "INFO" => Some(LogLevel::Info),
+                "LOG" => Some(LogLevel::Log),
+                _ => CUSTOM_LEVELS
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .position(|level| level.name == name)
+                    .and_then(|index| u16::try_from(index).ok())
+                    .map(LogLevel::Custom),
+            }
+        }
 
-    impl Logger {
-        fn format_message(level: LogLevel, message: &str) -> (String, String) {
-            let colour_code = match level {
+        /// RFC 5424 severity: Critical 2, Error 3, Warning 4, Info and Log 6, Debug 7, and
+        /// Success per `Logger::set_success_mapping` (Notice, 5, by default). Custom levels
+        /// take the severity of the built-in level they rank with.
+        pub fn syslog_severity(self) -> u8 {
+            match self {
+                LogLevel::Critical => 2,
+                LogLevel::Error => 3,
+                LogLevel::Warning => 4,
+                LogLevel::Success => Logger::success_mapping().syslog_severity,
+                LogLevel::Info | LogLevel::Log => 6,
+                LogLevel::Debug => 7,
+                LogLevel::Custom(index) => match Self::custom(index).severity {
+                    0 => 7,
+                    1 | 2 => 6,
+                    3 => 4,
+                    4 => 3,
+                    _ => 2,
+                },
+            }
+        }
+
+        /// OpenTelemetry `SeverityNumber`: 5 for Debug, 9 for Info and Log, 10 for Success,
+        /// 13 for Warning, 17 for Error and 21 for Critical, so higher is more severe.
+        pub fn otel_severity_number(self) -> u8 {
+            match self {
+                LogLevel::Debug => 5,
+                LogLevel::Log | LogLevel::Info => 9,
+                LogLevel::Success => 10,
+                LogLevel::Warning => 13,
+                LogLevel::Error => 17,
+                LogLevel::Critical => 21,
+                LogLevel::Custom(index) => match Self::custom(index).severity {
+                    0 => 5,
+                    1 | 2 => 9,
+                    3 => 13,
+                    4 => 17,
+                    _ => 21,
+                },
+            }
+        }
+
+        /// Rank used by level filters:
+        /// Debug < Log < Info = Success < Warning < Error < Critical.
+        pub fn severity(self) -> u8 {
+            match self {
+                LogLevel::Debug => 0,
+                LogLevel::Log => 1,
+                LogLevel::Info | LogLevel::Success => 2,
+                LogLevel::Warning => 3,
+                LogLevel::Error => 4,
+                LogLevel::Critical => 5,
+                LogLevel::Custom(index) => Self::custom(index).severity,
+            }
+        }
+
+        fn colour(&self) -> &'static str {
+            match self {
+                LogLevel::Critical => Colours::MAGENTA,
                 LogLevel::Error => Colours::RED,
                 LogLevel::Warning => Colours::YELLOW,
                 LogLevel::Debug => Colours::BLUE,
                 LogLevel::Success => Colours::GREEN,
                 LogLevel::Info => Colours::CYAN,
                 LogLevel::Log => Colours::WHITE,
+                LogLevel::Custom(index) => Self::custom(*index).colour,
+            }
+        }
+    }
+
+    /// How `LogLevel::Success`, which syslog, `log` and OpenTelemetry have no level for,
+    /// is translated by bridges and interop formats.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct SuccessMapping {
+        /// Syslog severity for Success records: 5 (Notice, the default) or 6 (Informational).
+        pub syslog_severity: u8,
+        /// Adds `outcome=success`, so the meaning survives being shown as Info.
+        pub outcome_field: bool,
+    }
+
+    impl Default for SuccessMapping {
+        fn default() -> Self {
+            SuccessMapping {
+                syslog_severity: 5,
+                outcome_field: true,
+            }
+        }
+    }
+
+    static SUCCESS_MAPPING: RwLock<Option<SuccessMapping>> = RwLock::new(None);
+
+    /// Which levels a module (or the whole logger) lets through.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum LevelFilter {
+        /// Nothing passes, not even errors.
+        Off,
+        /// Records at least as severe as the level pass (see `LogLevel::severity`).
+        Min(LogLevel),
+    }
+
+    impl LevelFilter {
+        pub fn allows(self, level: LogLevel) -> bool {
+            match self {
+                LevelFilter::Off => false,
+                LevelFilter::Min(min) => level.severity() >= min.severity(),
+            }
+        }
+    }
+
+    static LEVEL: RwLock<LevelFilter> = RwLock::new(LevelFilter::Min(LogLevel::Debug));
+
+    /// Per-module overrides of `LEVEL`, keyed by module glob.
+    static MODULE_LEVELS: RwLock<Vec<(String, LevelFilter)>> = RwLock::new(Vec::new());
+
+    /// Fields added to records from modules matching a glob (`Logger::add_module_fields`).
+    static MODULE_FIELDS: RwLock<Vec<(String, SharedFields)>> = RwLock::new(Vec::new());
+
+    /// The `MODULE_FIELDS` matching each module seen so far, merged; `None` when no rule
+    /// matches. Cleared whenever the rules change.
+    static MODULE_FIELD_CACHE: RwLock<BTreeMap<&'static str, Option<SharedFields>>> =
+        RwLock::new(BTreeMap::new());
+
+    /// Extra level filters for the console and the main log file alone.
+    static CONSOLE_LEVEL: RwLock<LevelFilter> = RwLock::new(LevelFilter::Min(LogLevel::Debug));
+
+    static FILE_LEVEL: RwLock<LevelFilter> = RwLock::new(LevelFilter::Min(LogLevel::Debug));
+
+    /// Minimum level while a quiet period is active, on top of the level filters.
+    static QUIET_PERIOD: RwLock<Option<LogLevel>> = RwLock::new(None);
+
+    /// Bumped by every quiet period, so an expiring one doesn't end its successor.
+    static QUIET_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+    /// A snapshot of the logger's counters, from `Logger::stats`.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct LoggerStats {
+        pub taken_at: SystemTime,
+        /// Records that passed the filters.
+        pub records: u64,
+        /// Error and Critical records since start-up or `Logger::reset_severity_counts`.
+        pub errors: usize,
+        pub warnings: usize,
+        pub last_error_at: Option<SystemTime>,
+        /// Records timestamped more than a second before an earlier one, i.e. times the
+        /// wall clock was stepped back (by NTP, say). Such records are still written;
+        /// intervals inside the logger are measured with `Instant` and don't notice.
+        pub clock_corrections: u64,
+        /// Bytes held by the non-blocking queue, the TCP writer's queue and the rate
+        /// limit windows.
+        pub memory_in_use: usize,
+        /// Records, lines and rate limit windows refused because of the memory budget.
+        pub memory_dropped: u64,
+        pub by_level: Vec<(LogLevel, u64)>,
+    }
+
+    impl LoggerStats {
+        /// One JSON object, with times as Unix milliseconds and levels by name:
+        /// `{"taken_at_ms":..,"records":3,"errors":1,"warnings":0,"last_error_at_ms":..,
+        /// "clock_corrections":0,"memory_in_use":0,"memory_dropped":0,
+        /// "by_level":{"INFO":2,"ERROR":1}}`.
+        pub fn to_json(&self) -> String {
+            let millis = |time: SystemTime| {
+                time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
             };
+            let last_error_at = self
+                .last_error_at
+                .map_or("null".to_owned(), |time| millis(time).to_string());
+            let mut json = format!(
+                "{{\"taken_at_ms\":{},\"records\":{},\"errors\":{},\"warnings\":{},\
+                 \"last_error_at_ms\":{last_error_at},\"clock_corrections\":{},\
+                 \"memory_in_use\":{},\"memory_dropped\":{},\"by_level\":{{",
+                millis(self.taken_at),
+                self.records,
+                self.errors,
+                self.warnings,
+                self.clock_corrections,
+                self.memory_in_use,
+                self.memory_dropped
+            );
+            for (index, (level, count)) in self.by_level.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                let _ = write!(json, "{}:{count}", json_string(level.as_str()));
+            }
+            json.push_str("}}");
+            json
+        }
+    }
 
-            let current_datetime = Local::now();
-            let date = current_datetime.format("%Y-%m-%d %H:%M:%S").to_string();
+    /// Whether a record at `millis` means the wall clock went back from `latest`. Records
+    /// from different threads can arrive slightly out of order, so small gaps don't count.
+    pub(crate) fn clock_stepped_back(latest: u64, millis: u64) -> bool {
+        latest.saturating_sub(millis) > 1_000
+    }
 
-            let console_fmt = format!("{date}{colour_code} [{}]: \x1b[0m {message} ", level.as_str());
-            let log_file_fmt = format!("{date} [{}]: {message} ", level.as_str());
-            (console_fmt, log_file_fmt)
+    /// `text` as a quoted JSON string.
+    pub(crate) fn json_string(text: &str) -> String {
+        let mut quoted = String::with_capacity(text.len() + 2);
+        quoted.push('"');
+        for c in text.chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                c if c.is_control() => {
+                    let _ = write!(quoted, "\\u{:04x}", u32::from(c));
+                }
+                c => quoted.push(c),
+            }
         }
+        quoted.push('"');
+        quoted
+    }
 
-        fn file_log(message: &str) -> io::Result<()> {
-            let mut script_name = env::args()
-                .next()
-                .map(|arg| {
-                    arg.split('/')
-                        .last()
-                        .unwrap_or(arg.as_str())
-                        .split('\\')
-                        .last()
-                        .unwrap_or(arg.as_str())
-                        .to_owned()
-                })
-                .unwrap_or("unknown".to_owned());
+    /// A failure inside the logger itself, reported to the error handler.
+    #[derive(Debug)]
+    pub enum LoggerError {
+        /// Stdout or stderr was closed (e.g. `app | head`); output to it is now disabled.
+        ConsoleClosed(io::Error),
+        ConsoleWrite(io::Error),
+        FileWrite { path: PathBuf, source: io::Error },
+        /// The log file can't be created or opened for writing, found by
+        /// `Logger::check_file_output`. `path` is absolute; `diagnostics` describes the
+        /// directory and its permissions.
+        FileUnwritable {
+            path: PathBuf,
+            source: io::Error,
+            diagnostics: String,
+        },
+        RemoteWrite(io::Error),
+        /// Settings that can't take effect together, e.g. routes without file output.
+        /// Each conflict is reported once.
+        Conflict(&'static str),
+        /// A setting was given a value the logger doesn't understand.
+        Config {
+            setting: String,
+            value: String,
+            expected: String,
+        },
+    }
 
-            if script_name.ends_with(".exe") {
-                script_name = script_name.replace(".exe", "");
+    impl Display for LoggerError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                LoggerError::ConsoleClosed(err) => {
+                    write!(f, "Console closed, console logging disabled: {err}")
+                }
+                LoggerError::ConsoleWrite(err) => write!(f, "Error writing to console: {err}"),
+                LoggerError::FileWrite { path, source } => {
+                    write!(f, "Error writing log file {}: {source}", path.display())
+                }
+                LoggerError::FileUnwritable {
+                    path,
+                    source,
+                    diagnostics,
+                } => {
+                    let path = path.display();
+                    write!(f, "Log file {path} is not writable: {source} ({diagnostics})")
+                }
+                LoggerError::RemoteWrite(err) => write!(f, "Error sending to remote logger: {err}"),
+                LoggerError::Conflict(conflict) => write!(f, "Conflicting settings: {conflict}"),
+                LoggerError::Config {
+                    setting,
+                    value,
+                    expected,
+                } => write!(f, "Invalid value {value:?} for {setting}, expected {expected}"),
+            }
+        }
+    }
+
+    impl LoggerError {
+        /// Stable identifier for monitoring: `console_closed`, `console_write`,
+        /// `file_disk_full`, `file_permission_denied`, `file_write`, `file_unwritable`,
+        /// `remote_write`, `conflict` or `config`.
+        pub fn code(&self) -> &'static str {
+            match self {
+                LoggerError::ConsoleClosed(_) => "console_closed",
+                LoggerError::ConsoleWrite(_) => "console_write",
+                LoggerError::FileWrite { source, .. } => match source.kind() {
+                    io::ErrorKind::StorageFull => "file_disk_full",
+                    io::ErrorKind::PermissionDenied => "file_permission_denied",
+                    _ => "file_write",
+                },
+                LoggerError::FileUnwritable { .. } => "file_unwritable",
+                LoggerError::RemoteWrite(_) => "remote_write",
+                LoggerError::Conflict(_) => "conflict",
+                LoggerError::Config { .. } => "config",
             }
-            let log_file_name = format!("{}.log", script_name);
+        }
 
-            let mut file = match OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(log_file_name)
-            {
-                Ok(file) => file,
-                Err(err) => {
-                    println!("Error opening log file: {}", err);
-                    return Err(err);
+        /// One JSON object with the `code`, the `message` and, where there is one, the
+        /// file `path` or the config `setting`.
+        pub fn to_json(&self) -> String {
+            let mut json = format!(
+                "{{\"code\":{},\"message\":{}",
+                json_string(self.code()),
+                json_string(&self.to_string())
+            );
+            match self {
+                LoggerError::FileWrite { path, .. } => {
+                    let path = path.display().to_string();
+                    let _ = write!(json, ",\"path\":{}", json_string(&path));
                 }
-            };
+                LoggerError::FileUnwritable {
+                    path, diagnostics, ..
+                } => {
+                    let path = path.display().to_string();
+                    let _ = write!(
+                        json,
+                        ",\"path\":{},\"diagnostics\":{}",
+                        json_string(&path),
+                        json_string(diagnostics)
+                    );
+                }
+                LoggerError::Config { setting, .. } => {
+                    let _ = write!(json, ",\"setting\":{}", json_string(setting));
+                }
+                _ => {}
+            }
+            json.push('}');
+            json
+        }
+    }
 
-            file.write_all(message.as_bytes())?;
-            file.write_all(b"\n")?;
-            Ok(())
+    impl std::error::Error for LoggerError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                LoggerError::ConsoleClosed(err)
+                | LoggerError::ConsoleWrite(err)
+                | LoggerError::RemoteWrite(err) => Some(err),
+                LoggerError::FileWrite { source, .. }
+                | LoggerError::FileUnwritable { source, .. } => Some(source),
+                LoggerError::Conflict(_) | LoggerError::Config { .. } => None,
+            }
         }
+    }
 
-        fn log_msg(level: LogLevel, message: Arguments) {
-            let formatted_message = format!("{}", message);
-            let (console_fmt, log_file_fmt) = Self::format_message(level, &formatted_message);
-            println!("{}", console_fmt);
-            let _ = Self::file_log(&log_file_fmt);
+    /// Returned by `Logger::try_log` when the record would have had to wait for the writer.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct WouldBlock;
+
+    impl Display for WouldBlock {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "log queue is full")
         }
+    }
 
-        pub fn log(args: Arguments) {
-            Self::log_msg(LogLevel::Log, args);
+    impl std::error::Error for WouldBlock {}
+
+    /// Records `Logger::try_log` can queue before callers get `WouldBlock`.
+    const NONBLOCKING_CAPACITY: usize = 1024;
+
+    /// Queue to the `firo-nonblocking` writer thread, `None` if it couldn't be started.
+    static NONBLOCKING_QUEUE: OnceLock<Option<std::sync::mpsc::SyncSender<LogRecord>>> =
+        OnceLock::new();
+
+    /// Runs first on the `firo-nonblocking` writer thread (see `Logger::set_worker_init`).
+    pub type WorkerInit = Box<dyn Fn() + Send + Sync>;
+
+    static WORKER_INIT: RwLock<Option<WorkerInit>> = RwLock::new(None);
+
+    /// Most records and longest wait per write of the `firo-nonblocking` writer.
+    static NONBLOCKING_BATCHING: RwLock<(usize, Duration)> = RwLock::new((1, Duration::ZERO));
+
+    /// Conflicts already reported, so each is only reported once.
+    static REPORTED_CONFLICTS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+    /// Feature keys `warn_deprecated!` has already warned about.
+    static WARNED_DEPRECATIONS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    /// Receives internal logger failures instead of the default stderr report.
+    pub type ErrorHandler = Box<dyn Fn(&LoggerError) + Send + Sync>;
+
+    static ERROR_HANDLER: RwLock<Option<ErrorHandler>> = RwLock::new(None);
+
+    static STDOUT_CLOSED: AtomicBool = AtomicBool::new(false);
+
+    static STDERR_CLOSED: AtomicBool = AtomicBool::new(false);
+
+    /// Console stream a level is printed to.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Stream {
+        Stdout,
+        Stderr,
+    }
+
+    impl Stream {
+        fn closed_flag(self) -> &'static AtomicBool {
+            match self {
+                Stream::Stdout => &STDOUT_CLOSED,
+                Stream::Stderr => &STDERR_CLOSED,
+            }
         }
+    }
 
-        pub fn error(args: Arguments) {
-            Self::log_msg(LogLevel::Error, args);
+    /// Per-level overrides; levels without one print to `DEFAULT_STREAM`.
+    static LEVEL_STREAMS: RwLock<Vec<(LogLevel, Stream)>> = RwLock::new(Vec::new());
+
+    /// Set by `Logger::set_all_streams`, so it also covers levels registered later.
+    static DEFAULT_STREAM: RwLock<Stream> = RwLock::new(Stream::Stdout);
+
+    /// An immutable set of `key=value` fields that many records can point to.
+    pub type SharedFields = Arc<[(String, String)]>;
+
+    /// A single log entry, built before any formatting takes place.
+    #[derive(Debug, Clone)]
+    pub struct LogRecord {
+        pub level: LogLevel,
+        pub message: String,
+        /// Taken from `Logger::now()` when the record is created on the calling thread, so
+        /// it reflects when the log call was made rather than when an output got to it.
+        pub timestamp: SystemTime,
+        /// Where the record was logged from, when logged through the macros.
+        pub callsite: Option<Callsite>,
+        /// Time-sortable UUIDv7, set when event ids are enabled.
+        pub event_id: Option<String>,
+        /// Position of the record in this process's log stream, when sequence numbers are enabled.
+        /// Assigned before any output is written; records from one thread always get
+        /// increasing numbers.
+        pub sequence: Option<u64>,
+        /// Tenant active on the logging thread (see `Logger::set_tenant`).
+        pub tenant_id: Option<String>,
+        /// Extra `key=value` fields, rendered after the message.
+        pub metadata: Vec<(String, String)>,
+        /// Fields shared by many records (such as enricher snapshots), held by reference
+        /// rather than copied into every record. Rendered after `metadata`.
+        pub shared_metadata: Vec<SharedFields>,
+        /// Units of numeric `metadata` fields (see `field_duration` and `field_bytes`):
+        /// text layouts show them humanized, JSON as plain numbers.
+        pub field_units: Vec<(String, FieldUnit)>,
+    }
+
+    impl LogRecord {
+        /// The value of the first `metadata` or `shared_metadata` field named `key`.
+        pub fn field(&self, key: &str) -> Option<&str> {
+            let shared = self.shared_metadata.iter().flat_map(|shared| shared.iter());
+            self.metadata
+                .iter()
+                .chain(shared)
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_str())
         }
 
-        pub fn warning(args: Arguments) {
-            Self::log_msg(LogLevel::Warning, args);
+        /// Approximate bytes held by this record, as charged to the memory budget. Shared
+        /// fields are not counted: the record only holds a reference to them.
+        pub(crate) fn buffered_size(&self) -> usize {
+            let pair = std::mem::size_of::<(String, String)>();
+            std::mem::size_of::<LogRecord>()
+                + self.message.len()
+                + self.event_id.as_ref().map_or(0, String::len)
+                + self.tenant_id.as_ref().map_or(0, String::len)
+                + self
+                    .metadata
+                    .iter()
+                    .map(|(key, value)| pair + key.len() + value.len())
+                    .sum::<usize>()
+                + self.shared_metadata.len() * std::mem::size_of::<SharedFields>()
         }
 
-        pub fn debug(args: Arguments) {
-            Self::log_msg(LogLevel::Debug, args);
+        /// The unit of the `metadata` field named `key`, if it is a measure.
+        pub fn field_unit(&self, key: &str) -> Option<FieldUnit> {
+            self.field_units
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, unit)| *unit)
         }
 
-        pub fn info(args: Arguments) {
-            Self::log_msg(LogLevel::Info, args);
+        /// Takes `message` by value when given a `String`, so an already rendered
+        /// message isn't copied again.
+        pub fn new(level: LogLevel, message: impl Into<String>) -> Self {
+            LogRecord {
+                level,
+                message: message.into(),
+                timestamp: Logger::now(),
+                callsite: None,
+                event_id: None,
+                sequence: None,
+                tenant_id: None,
+                metadata: Vec::new(),
+                shared_metadata: Vec::new(),
+                field_units: Vec::new(),
+            }
         }
+    }
 
-        pub fn success(args: Arguments) {
-            Self::log_msg(LogLevel::Success, args);
+    /// The unit of a numeric field value.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum FieldUnit {
+        Millis,
+        Bytes,
+    }
+
+    impl FieldUnit {
+        /// `850ms`, `1.2s`, `2m05s` or `3h20m` for durations; `512 B`, `3.4 MiB` for sizes.
+        pub fn humanize(self, value: u64) -> String {
+            match self {
+                FieldUnit::Millis => match value {
+                    0..1_000 => format!("{value}ms"),
+                    1_000..60_000 => format!("{:.1}s", value as f64 / 1_000.0),
+                    60_000..3_600_000 => format!("{}m{:02}s", value / 60_000, value / 1_000 % 60),
+                    _ => format!("{}h{:02}m", value / 3_600_000, value / 60_000 % 60),
+                },
+                FieldUnit::Bytes => {
+                    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+                    if value < 1024 {
+                        return format!("{value} B");
+                    }
+                    let mut scaled = value as f64 / 1024.0;
+                    let mut unit = 0;
+                    while scaled >= 1024.0 && unit < UNITS.len() - 1 {
+                        scaled /= 1024.0;
+                        unit += 1;
+                    }
+                    format!("{scaled:.1} {}", UNITS[unit])
+                }
+            }
         }
     }
 
-    #[macro_export]
-    macro_rules! log_info {
-        ($($arg:tt)*) => {
-            $crate::logger::Logger::info(format_args!($($arg)*))
-        };
+    /// A field value for `log_with_metadata!` and the `key = value` fields of the level
+    /// macros: anything `Display`, or a measure from `field_duration` or `field_bytes`.
+    pub trait FieldValue {
+        /// Writes the value as stored on the record.
+        fn write_value(&self, out: &mut String) -> fmt::Result;
+
+        /// The unit text layouts humanize the value in, if it is a measure.
+        fn unit(&self) -> Option<FieldUnit> {
+            None
+        }
     }
 
-    #[macro_export]
-    macro_rules! log_debug {
-        ($($arg:tt)*) => {
-            $crate::logger::Logger::debug(format_args!($($arg)*))
-        };
+    impl<T: Display + ?Sized> FieldValue for T {
+        fn write_value(&self, out: &mut String) -> fmt::Result {
+            write!(out, "{self}")
+        }
     }
 
-    #[macro_export]
-    macro_rules! log_warning {
-        ($($arg:tt)*) => {
-            $crate::logger::Logger::warning(format_args!($($arg)*))
-        };
+    /// A number with a unit; see `field_duration` and `field_bytes`.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct FieldMeasure {
+        pub value: u64,
+        pub unit: FieldUnit,
     }
 
-    #[macro_export]
-    macro_rules! log_success {
-        ($($arg:tt)*) => {
-            $crate::logger::Logger::success(format_args!($($arg)*))
-        };
+    impl FieldValue for FieldMeasure {
+        fn write_value(&self, out: &mut String) -> fmt::Result {
+            write!(out, "{}", self.value)
+        }
+
+        fn unit(&self) -> Option<FieldUnit> {
+            Some(self.unit)
+        }
     }
 
-    #[macro_export]
-    macro_rules! log_error {
-        ($($arg:tt)*) => {
-            $crate::logger::Logger::error(format_args!($($arg)*))
-        };
+    /// A duration field shown as `1.2s` in text and as milliseconds in JSON:
+    /// `log_info!(took = field_duration(elapsed_ms), "request done")`.
+    pub fn field_duration(millis: u64) -> FieldMeasure {
+        FieldMeasure {
+            value: millis,
+            unit: FieldUnit::Millis,
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// A size field shown as `3.4 MiB` in text and as bytes in JSON.
+    pub fn field_bytes(bytes: u64) -> FieldMeasure {
+        FieldMeasure {
+            value: bytes,
+            unit: FieldUnit::Bytes,
+        }
+    }
 
-    #[test]
-    fn test_format_message_error() {
-        let (console, file) = Logger::format_message(LogLevel::Error, "This is an error message");
-        assert!(console.contains("[ERROR]"));
-        assert!(file.contains("[ERROR]"));
+    /// Module and source location of a logging macro invocation.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Callsite {
+        pub module: &'static str,
+        pub file: &'static str,
+        pub line: u32,
     }
 
-    #[test]
-    fn test_format_message_info() {
-        let (console, file) = Logger::format_message(LogLevel::Info, "Information log");
-        assert!(console.contains("[INFO]"));
-        assert!(file.contains("[INFO]"));
+    impl Callsite {
+        /// The file path as it should be displayed, after any configured prefix is stripped.
+        pub(crate) fn display_file(&self) -> &'static str {
+            match SOURCE_PREFIX.read().unwrap().as_deref() {
+                Some(prefix) => trim_source_path(self.file, prefix),
+                None => self.file,
+            }
+        }
     }
 
-    #[test]
-    fn test_format_message_debug() {
-        let (console, file) = Logger::format_message(LogLevel::Debug, "Debugging message");
-        assert!(console.contains("[DEBUG]"));
-        assert!(file.contains("[DEBUG]"));
+    /// Source of record timestamps, swappable for deterministic tests.
+    pub trait Clock: Send + Sync {
+        fn now(&self) -> SystemTime;
     }
 
-    #[test]
-    fn test_log_success() {
-        let (console, _) = Logger::format_message(LogLevel::Success, "Successful operation");
-        assert!(console.contains(Colours::GREEN)); // Ensure it uses the correct color
-        assert!(console.contains("[SUCCESS]"));
+    /// The wall clock; used unless another clock is installed.
+    #[derive(Debug, Default)]
+    pub struct SystemClock;
+
+    impl Clock for SystemClock {
+        fn now(&self) -> SystemTime {
+            SystemTime::now()
+        }
     }
-    
-    #[test]
-    fn test_log_warning() {
-        let (console, _) = Logger::format_message(LogLevel::Warning, "Warning message");
-        assert!(console.contains(Colours::YELLOW)); // Ensure correct color is applied
-        assert!(console.contains("[WARNING]"));
+
+    /// Always returns the same instant.
+    #[derive(Debug)]
+    pub struct FixedClock(pub SystemTime);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> SystemTime {
+            self.0
+        }
+    }
+
+    /// Starts at a given instant and advances by `step` on every call.
+    #[derive(Debug)]
+    pub struct SteppingClock {
+        start: SystemTime,
+        step: Duration,
+        ticks: AtomicU32,
+    }
+
+    impl SteppingClock {
+        pub fn new(start: SystemTime, step: Duration) -> Self {
+            SteppingClock {
+                start,
+                step,
+                ticks: AtomicU32::new(0),
+            }
+        }
+    }
+
+    impl Clock for SteppingClock {
+        fn now(&self) -> SystemTime {
+            let ticks = self.ticks.fetch_add(1, Ordering::Relaxed);
+            self.start + self.step * ticks
+        }
+    }
+
+    static CLOCK: RwLock<Option<Box<dyn Clock>>> = RwLock::new(None);
+
+    /// Where formatted records are written.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Output {
+        ConsoleAndFile,
+        Console,
+        File,
+        /// Records are still formatted, then discarded.
+        None,
+    }
+
+    static OUTPUT: RwLock<Output> = RwLock::new(Output::ConsoleAndFile);
+
+    /// Whether an unwritable log file at start-up means console-only output rather than
+    /// an error (see `Logger::set_file_fallback`).
+    static FILE_FALLBACK: AtomicBool = AtomicBool::new(false);
+
+    /// Layout of console or log file lines (see `Logger::set_console_format` and
+    /// `Logger::set_file_format`).
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum OutputFormat {
+        /// `2024-10-01 10:32:45 [INFO]: message`
+        Text,
+        /// Relative timestamps, level badges and source locations for local development.
+        Pretty,
+        /// `2024-10-01 10:32:45 INFO    db::pool          message`, with the level and
+        /// module padded to fixed widths so lines from different modules align.
+        Columns(ColumnWidths),
+        /// Comma-separated values in the columns set with `Logger::set_csv_columns`,
+        /// quoted where needed. Log files get a header row when they are created.
+        Csv,
+        /// Like `Csv`, separated by tabs, with tabs and newlines in values escaped.
+        Tsv,
+        /// RFC 5424 syslog lines for rsyslog and friends:
+        /// `<14>1 2024-10-01T10:32:45.123456Z host app 4242 - [firo@32473 user="alice"] message`.
+        /// The severity comes from `LogLevel::syslog_severity`.
+        Syslog(SyslogFacility),
+        /// The layout set with `Logger::set_pattern`, e.g. `%d{%H:%M:%S} [%l] %m - %msg %kv`.
+        Pattern,
+        /// One JSON object per record:
+        /// `{"timestamp":"..","level":"INFO","severity_number":9,"module":"app::db",
+        /// "message":"..","fields":{..}}`, with the severity number on the scale set with
+        /// `Logger::set_json_severity_scale`.
+        /// `pretty` spreads each object over indented lines, for reading rather than
+        /// shipping.
+        Json { pretty: bool },
+    }
+
+    /// Where the `Text` and `Pretty` layouts put a record's fields.
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    pub enum FieldPlacement {
+        /// The layout's own choice: inline for `Text`, a block for `Pretty`.
+        #[default]
+        Layout,
+        /// ` key=value` pairs after the message.
+        Inline,
+        /// One `key = value` line per field below the message, with the keys aligned.
+        Block,
+        /// Not shown.
+        Omitted,
+    }
+
+    /// How one writer renders record fields (see `Logger::set_console_fields` and
+    /// `Logger::set_file_fields`). Machine-readable layouts always keep every field.
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    pub struct FieldStyle {
+        pub placement: FieldPlacement,
+        /// Orders fields by key, so the same fields read the same in every run.
+        pub sorted: bool,
+    }
+
+    static CONSOLE_FIELDS: RwLock<FieldStyle> = RwLock::new(FieldStyle {
+        placement: FieldPlacement::Layout,
+        sorted: false,
+    });
+
+    static FILE_FIELDS: RwLock<FieldStyle> = RwLock::new(FieldStyle {
+        placement: FieldPlacement::Layout,
+        sorted: false,
+    });
+
+    /// Scale of the `severity_number` member of JSON lines.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum SeverityScale {
+        /// `LogLevel::otel_severity_number` (the default).
+        OpenTelemetry,
+        /// `LogLevel::syslog_severity`, where lower is more severe.
+        Syslog,
+    }
+
+    static JSON_SEVERITY_SCALE: RwLock<SeverityScale> = RwLock::new(SeverityScale::OpenTelemetry);
+
+    /// One part of a `Logger::set_pattern` layout.
+    #[derive(Debug, Clone, PartialEq)]
+    pub(crate) enum PatternPiece {
+        Literal(String),
+        /// `%d`, or `%d{strftime}` with the chrono feature.
+        Timestamp(Option<String>),
+        Level,
+        Module,
+        File,
+        Line,
+        Thread,
+        Message,
+        Fields,
+    }
+
+    static PATTERN: RwLock<Vec<PatternPiece>> = RwLock::new(Vec::new());
+
+    /// Facility of `OutputFormat::Syslog` lines (RFC 5424, section 6.2.1).
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum SyslogFacility {
+        Kern = 0,
+        User = 1,
+        Mail = 2,
+        Daemon = 3,
+        Auth = 4,
+        Syslog = 5,
+        Lpr = 6,
+        News = 7,
+        Uucp = 8,
+        Cron = 9,
+        AuthPriv = 10,
+        Ftp = 11,
+        Local0 = 16,
+        Local1 = 17,
+        Local2 = 18,
+        Local3 = 19,
+        Local4 = 20,
+        Local5 = 21,
+        Local6 = 22,
+        Local7 = 23,
+    }
+
+    impl SyslogFacility {
+        pub fn code(self) -> u8 {
+            self as u8
+        }
+    }
+
+    /// A column of `OutputFormat::Csv` and `OutputFormat::Tsv` lines.
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum CsvColumn {
+        Timestamp,
+        Level,
+        Module,
+        Message,
+        /// A metadata field, empty when the record doesn't have it.
+        Field(String),
+    }
+
+    /// Empty means timestamp, level, module and message.
+    static CSV_COLUMNS: RwLock<Vec<CsvColumn>> = RwLock::new(Vec::new());
+
+    static FILE_FORMAT: RwLock<OutputFormat> = RwLock::new(OutputFormat::Text);
+
+    /// Column widths for `OutputFormat::Columns`. Longer modules keep their last characters.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct ColumnWidths {
+        pub level: usize,
+        pub module: usize,
+    }
+
+    impl Default for ColumnWidths {
+        fn default() -> Self {
+            ColumnWidths {
+                level: 8,
+                module: 24,
+            }
+        }
+    }
+
+    /// How the caller's module path is shown in text lines.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum ModuleStyle {
+        /// Not shown (the default).
+        Hidden,
+        /// `app::db::pool::handler`
+        Full,
+        /// `app.db.pool::handler`
+        Dotted,
+        /// Only the last N segments, e.g. `pool::handler` for 2.
+        LastSegments(usize),
+    }
+
+    static MODULE_STYLE: RwLock<ModuleStyle> = RwLock::new(ModuleStyle::Hidden);
+
+    static STRIP_CRATE_NAME: AtomicBool = AtomicBool::new(false);
+
+    static LOG_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+    /// Zero means no budget.
+    static DISK_BUDGET: AtomicU64 = AtomicU64::new(0);
+
+    /// Milliseconds after `PROCESS_START` of the last budget check.
+    static LAST_BUDGET_CHECK: AtomicU64 = AtomicU64::new(0);
+
+    static PROCESS_START: OnceLock<std::time::Instant> = OnceLock::new();
+
+    /// `-YYYYMMDD-HHMMSS-PID` appended to log file stems once a session is started.
+    static SESSION: RwLock<Option<String>> = RwLock::new(None);
+
+    static MARKUP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+    static SOURCE_PREFIX: RwLock<Option<String>> = RwLock::new(None);
+
+    static CONSOLE_FORMAT: RwLock<OutputFormat> = RwLock::new(OutputFormat::Text);
+
+    static PRETTY_START: OnceLock<SystemTime> = OnceLock::new();
+
+    /// Replacement for `println!` when writing console lines.
+    pub type ConsoleHook = Box<dyn Fn(&str) + Send + Sync>;
+
+    static CONSOLE_HOOK: RwLock<Option<ConsoleHook>> = RwLock::new(None);
+
+    #[cfg(feature = "log")]
+    static LOG_BACKEND: RwLock<Option<Box<dyn log::Log>>> = RwLock::new(None);
+
+    static REMOTE_WRITER: RwLock<Option<RemoteWriter>> = RwLock::new(None);
+
+    static TCP_WRITER: RwLock<Option<TcpWriter>> = RwLock::new(None);
+
+    static LOKI_WRITER: RwLock<Option<LokiWriter>> = RwLock::new(None);
+
+    #[cfg(feature = "syslog")]
+    static SYSLOG_WRITER: RwLock<Option<SyslogWriter>> = RwLock::new(None);
+
+    static COLOURS_ENABLED: AtomicBool = AtomicBool::new(cfg!(feature = "colours"));
+
+    static PER_THREAD_FILES: AtomicBool = AtomicBool::new(false);
+
+    static PER_TENANT_FILES: AtomicBool = AtomicBool::new(false);
+
+    #[cfg(feature = "event-id")]
+    static INCLUDE_EVENT_ID: AtomicBool = AtomicBool::new(false);
+
+    static INCLUDE_SEQUENCE: AtomicBool = AtomicBool::new(false);
+
+    static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+    static UTC_TIMESTAMPS: AtomicBool = AtomicBool::new(false);
+
+    /// Settings `Logger::enable_test_mode` replaced, for `disable_test_mode` to restore.
+    struct SavedSettings {
+        clock: Option<Box<dyn Clock>>,
+        utc: bool,
+        colours: bool,
+    }
+
+    static TEST_MODE_SAVED: Mutex<Option<SavedSettings>> = Mutex::new(None);
+
+    #[cfg(feature = "chrono")]
+    const DATETIME_PATTERN: &str = "%Y-%m-%d %H:%M:%S";
+
+    /// Replaces `DATETIME_PATTERN` once set with `Logger::set_datetime_format`.
+    #[cfg(feature = "chrono")]
+    static DATETIME_FORMAT: RwLock<Option<String>> = RwLock::new(None);
+
+    /// How record timestamps are rendered.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum TimestampFormat {
+        /// `2024-10-01 10:32:45`
+        DateTime,
+        /// `2024-10-01 10:32:45.123456789`
+        DateTimeNanos,
+        /// Seconds since the Unix epoch.
+        UnixSeconds,
+        /// Milliseconds since the Unix epoch.
+        UnixMillis,
+        /// Nanoseconds since the Unix epoch.
+        UnixNanos,
+    }
+
+    static TIMESTAMP_FORMAT: RwLock<TimestampFormat> = RwLock::new(TimestampFormat::DateTime);
+
+    static CAPTURE: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+    thread_local! {
+        static THREAD_CAPTURE: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+        static TENANT: RefCell<Option<String>> = const { RefCell::new(None) };
+        static BREADCRUMBS: RefCell<Option<Breadcrumbs>> = const { RefCell::new(None) };
+        /// Errors collected for `Logger::emit` instead of going to the error handler.
+        static EMIT_ERRORS: RefCell<Option<Vec<LoggerError>>> = const { RefCell::new(None) };
+        /// Threshold set with `Logger::override_level`.
+        static LEVEL_OVERRIDE: RefCell<Option<LogLevel>> = const { RefCell::new(None) };
+    }
+
+    /// Debug records held back by `Logger::breadcrumbs`, oldest first.
+    #[derive(Debug)]
+    struct Breadcrumbs {
+        capacity: usize,
+        records: VecDeque<LogRecord>,
+    }
+
+    /// Ends a breadcrumb scope when dropped, discarding records that weren't flushed.
+    #[must_use = "breadcrumbs are discarded as soon as the guard is dropped"]
+    #[derive(Debug)]
+    pub struct BreadcrumbGuard {
+        previous: Option<Breadcrumbs>,
+    }
+
+    impl Drop for BreadcrumbGuard {
+        fn drop(&mut self) {
+            let previous = self.previous.take();
+            BREADCRUMBS.with(|crumbs| *crumbs.borrow_mut() = previous);
+        }
+    }
+
+    /// Restores the thread's previous level override when dropped.
+    #[must_use = "the level override ends as soon as the guard is dropped"]
+    #[derive(Debug)]
+    pub struct LevelGuard {
+        previous: Option<LogLevel>,
+    }
+
+    impl Drop for LevelGuard {
+        fn drop(&mut self) {
+            let previous = self.previous.take();
+            LEVEL_OVERRIDE.with(|level| *level.borrow_mut() = previous);
+        }
+    }
+
+    /// Sends matching records to an additional log file.
+    ///
+    /// A route with no module pattern and no levels matches every record.
+    #[derive(Debug, Clone)]
+    pub struct Route {
+        path: PathBuf,
+        module: Option<String>,
+        levels: Option<Vec<LogLevel>>,
+        min_level: Option<LogLevel>,
+        max_level: Option<LogLevel>,
+        /// Required fields, with the required value if any.
+        fields: Vec<(String, Option<String>)>,
+        retention: Option<Duration>,
+    }
+
+    impl Route {
+        pub fn to_file<P: Into<PathBuf>>(path: P) -> Self {
+            Route {
+                path: path.into(),
+                module: None,
+                levels: None,
+                min_level: None,
+                max_level: None,
+                fields: Vec::new(),
+                retention: None,
+            }
+        }
+
+        /// Only route records whose module matches `pattern`, where `*` matches any text
+        /// (e.g. `myapp::audit::*`).
+        pub fn module(mut self, pattern: &str) -> Self {
+            self.module = Some(pattern.to_owned());
+            self
+        }
+
+        /// Only route records at one of `levels`.
+        pub fn levels(mut self, levels: &[LogLevel]) -> Self {
+            self.levels = Some(levels.to_vec());
+            self
+        }
+
+        /// Only route records at least as severe as `level` (see `LogLevel::severity`).
+        pub fn min_level(mut self, level: LogLevel) -> Self {
+            self.min_level = Some(level);
+            self
+        }
+
+        /// Only route records at most as severe as `level`, e.g. `Info` keeps warnings
+        /// and errors out of a file that has its own error route.
+        pub fn max_level(mut self, level: LogLevel) -> Self {
+            self.max_level = Some(level);
+            self
+        }
+
+        /// Only route records with a `key` field equal to `value`, e.g. `("audit", "true")`.
+        pub fn field(mut self, key: &str, value: &str) -> Self {
+            self.fields.push((key.to_owned(), Some(value.to_owned())));
+            self
+        }
+
+        /// Only route records that have a `key` field, whatever its value.
+        pub fn has_field(mut self, key: &str) -> Self {
+            self.fields.push((key.to_owned(), None));
+            self
+        }
+
+        /// Gives records this route matches a retention hint of `ttl`, unless they carry
+        /// a shorter one, for the janitor `Logger::expire_records` to honour.
+        pub fn retention(mut self, ttl: Duration) -> Self {
+            self.retention = Some(ttl);
+            self
+        }
+
+        pub fn matches(&self, record: &LogRecord) -> bool {
+            let fields_match = self.fields.iter().all(|(key, expected)| {
+                match (record.field(key), expected) {
+                    (Some(value), Some(expected)) => value == expected,
+                    (found, None) => found.is_some(),
+                    (None, Some(_)) => false,
+                }
+            });
+            if !fields_match {
+                return false;
+            }
+            if let Some(levels) = &self.levels {
+                if !levels.contains(&record.level) {
+                    return false;
+                }
+            }
+            let severity = record.level.severity();
+            if self.min_level.is_some_and(|min| severity < min.severity())
+                || self.max_level.is_some_and(|max| severity > max.severity())
+            {
+                return false;
+            }
+            match (&self.module, record.callsite) {
+                (Some(pattern), Some(callsite)) => glob_match(pattern, callsite.module),
+                (Some(_), None) => false,
+                (None, _) => true,
+            }
+        }
+    }
+
+    static ERROR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    static WARNING_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    /// Records written per level, in the order levels were first seen.
+    static LEVEL_COUNTS: Mutex<Vec<(LogLevel, u64)>> = Mutex::new(Vec::new());
+
+    /// Unix milliseconds of the last Error or Critical record, zero if none.
+    static LAST_ERROR_AT: AtomicU64 = AtomicU64::new(0);
+
+    /// Latest record timestamp seen, in Unix milliseconds.
+    static LATEST_TIMESTAMP: AtomicU64 = AtomicU64::new(0);
+
+    /// Times the wall clock was seen stepping back (see `LoggerStats::clock_corrections`).
+    static CLOCK_CORRECTIONS: AtomicU64 = AtomicU64::new(0);
+
+    static FLUSH_ON_CRITICAL: AtomicBool = AtomicBool::new(false);
+
+    /// Earliest `expires` (Unix seconds) written by this process and not yet removed,
+    /// or `u64::MAX`.
+    static NEXT_EXPIRY: AtomicU64 = AtomicU64::new(u64::MAX);
+
+    static ROUTES: RwLock<Vec<Route>> = RwLock::new(Vec::new());
+
+    /// Fields produced by an enrichment provider, refreshed on a background thread.
+    pub(crate) struct Enricher {
+        cache: Arc<RwLock<SharedFields>>,
+    }
+
+    impl Enricher {
+        /// Calls `provider` now and then every `interval` on a `firo-enricher` thread,
+        /// which exits once the enricher is dropped.
+        pub(crate) fn spawn<F>(interval: Duration, provider: F) -> Self
+        where
+            F: Fn() -> Vec<(String, String)> + Send + 'static,
+        {
+            let cache = Arc::new(RwLock::new(Arc::from(provider())));
+            let weak = Arc::downgrade(&cache);
+            // If the thread can't be spawned the first snapshot simply never refreshes.
+            let _ = thread::Builder::new()
+                .name("firo-enricher".to_owned())
+                .spawn(move || loop {
+                    thread::sleep(interval);
+                    let Some(cache) = weak.upgrade() else {
+                        break;
+                    };
+                    let fields = Arc::from(provider());
+                    *cache.write().unwrap() = fields;
+                });
+            Enricher { cache }
+        }
+
+        /// The latest snapshot, shared with every record that carries it.
+        pub(crate) fn fields(&self) -> SharedFields {
+            Arc::clone(&self.cache.read().unwrap())
+        }
+    }
+
+    static ENRICHERS: RwLock<Vec<Enricher>> = RwLock::new(Vec::new());
+
+    /// Restores the previous tenant of the thread when dropped.
+    #[must_use = "the tenant is reset as soon as the guard is dropped"]
+    #[derive(Debug)]
+    pub struct TenantGuard {
+        previous: Option<String>,
+    }
+
+    impl Drop for TenantGuard {
+        fn drop(&mut self) {
+            let previous = self.previous.take();
+            TENANT.with(|tenant| *tenant.borrow_mut() = previous);
+        }
+    }
+
+    /// The logging context of a thread, for carrying into worker threads such as a rayon pool:
+    ///
+    /// ```ignore
+    /// let context = LogContext::current();
+    /// items.par_iter().for_each(context.wrap(|item| log_info!("processing {item}")));
+    /// ```
+    #[derive(Debug, Clone, Default)]
+    pub struct LogContext {
+        tenant_id: Option<String>,
+    }
+
+    impl LogContext {
+        /// Captures the context of the current thread.
+        pub fn current() -> Self {
+            LogContext {
+                tenant_id: TENANT.with(|tenant| tenant.borrow().clone()),
+            }
+        }
+
+        /// Runs `f` with this context installed on the current thread, restoring the
+        /// thread's own context afterwards.
+        pub fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+            let previous = TENANT.with(|tenant| tenant.replace(self.tenant_id.clone()));
+            let _guard = TenantGuard { previous };
+            f()
+        }
+
+        /// Wraps `f` so every call runs inside `scope`, whichever thread makes it.
+        pub fn wrap<A, R, F>(&self, f: F) -> impl Fn(A) -> R + Send + Sync
+        where
+            F: Fn(A) -> R + Send + Sync,
+        {
+            let context = self.clone();
+            move |arg| context.scope(|| f(arg))
+        }
+    }
+
+    /// Zero means unlimited.
+    static MAX_MESSAGE_LEN: AtomicUsize = AtomicUsize::new(0);
+
+    struct LimitedWriter {
+        buffer: String,
+        limit: usize,
+        truncated: bool,
+    }
+
+    impl fmt::Write for LimitedWriter {
+        fn write_str(&mut self, text: &str) -> fmt::Result {
+            if self.limit == 0 || self.buffer.len() + text.len() <= self.limit {
+                self.buffer.push_str(text);
+                return Ok(());
+            }
+            let mut end = self.limit - self.buffer.len();
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.buffer.push_str(&text[..end]);
+            self.truncated = true;
+            // Stops the formatting machinery from producing the rest.
+            Err(fmt::Error)
+        }
+    }
+
+    /// Displays arbitrary bytes safely: valid UTF-8 is kept (with control characters escaped)
+    /// and invalid bytes are shown as `\xNN`.
+    #[derive(Debug, Clone, Copy)]
+    pub struct EscapedBytes<'a>(pub &'a [u8]);
+
+    impl Display for EscapedBytes<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            for chunk in self.0.utf8_chunks() {
+                for c in chunk.valid().chars() {
+                    if c.is_control() {
+                        write!(f, "{}", c.escape_default())?;
+                    } else {
+                        f.write_char(c)?;
+                    }
+                }
+                for byte in chunk.invalid() {
+                    write!(f, "\\x{byte:02x}")?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Predicate deciding whether a record is logged; returning `false` drops it.
+    pub type RecordFilter = Box<dyn Fn(&LogRecord) -> bool + Send + Sync>;
+
+    static FILTERS: RwLock<Vec<RecordFilter>> = RwLock::new(Vec::new());
+
+    /// `(file, line)` of log statements silenced with `Logger::disable_callsite`.
+    static DISABLED_CALLSITES: RwLock<Vec<(String, u32)>> = RwLock::new(Vec::new());
+
+    /// Percentiles of the durations recorded under one name.
+    ///
+    /// Durations are bucketed on a power-of-two microsecond scale, so percentiles are
+    /// upper bounds accurate to within a factor of two (and never above `max`).
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct DurationSummary {
+        pub count: u64,
+        pub p50: Duration,
+        pub p95: Duration,
+        pub p99: Duration,
+        pub max: Duration,
+    }
+
+    #[derive(Debug)]
+    struct Histogram {
+        buckets: [u64; 64],
+        count: u64,
+        max: Duration,
+    }
+
+    impl Histogram {
+        fn new() -> Self {
+            Histogram {
+                buckets: [0; 64],
+                count: 0,
+                max: Duration::ZERO,
+            }
+        }
+
+        fn record(&mut self, duration: Duration) {
+            let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+            let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+            self.buckets[bucket.min(63)] += 1;
+            self.count += 1;
+            self.max = self.max.max(duration);
+        }
+
+        fn percentile(&self, percent: u64) -> Duration {
+            let target = (self.count * percent).div_ceil(100).max(1);
+            let mut seen = 0;
+            for (bucket, count) in self.buckets.iter().enumerate() {
+                seen += count;
+                if seen >= target {
+                    let upper = Duration::from_micros(1u64 << bucket.min(62));
+                    return upper.min(self.max);
+                }
+            }
+            self.max
+        }
+
+        fn summary(&self) -> DurationSummary {
+            DurationSummary {
+                count: self.count,
+                p50: self.percentile(50),
+                p95: self.percentile(95),
+                p99: self.percentile(99),
+                max: self.max,
+            }
+        }
+    }
+
+    static HISTOGRAMS: Mutex<BTreeMap<String, Histogram>> = Mutex::new(BTreeMap::new());
+
+    /// At most this many records per callsite in each window (see `Logger::set_rate_limit`).
+    static RATE_LIMIT: RwLock<Option<(u32, Duration)>> = RwLock::new(None);
+
+    static RATE_WINDOWS: Mutex<BTreeMap<(&'static str, u32), RateWindow>> =
+        Mutex::new(BTreeMap::new());
+
+    /// Bytes the logger's buffers may hold (see `Logger::set_memory_budget`); zero means
+    /// no budget.
+    static MEMORY_BUDGET: AtomicUsize = AtomicUsize::new(0);
+
+    static MEMORY_IN_USE: AtomicUsize = AtomicUsize::new(0);
+
+    /// Records, lines and rate-limit windows refused because of the memory budget.
+    static MEMORY_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+    /// Accounts `bytes` of buffered data, or counts a drop and returns `false` when that
+    /// would go over the memory budget.
+    pub(crate) fn reserve_memory(bytes: usize) -> bool {
+        let budget = MEMORY_BUDGET.load(Ordering::Relaxed);
+        let in_use = MEMORY_IN_USE.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if budget != 0 && in_use > budget {
+            MEMORY_IN_USE.fetch_sub(bytes, Ordering::Relaxed);
+            MEMORY_DROPPED.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Gives back `bytes` taken with `reserve_memory`.
+    pub(crate) fn release_memory(bytes: usize) {
+        MEMORY_IN_USE.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    /// What one rate limit window costs in the memory budget.
+    const RATE_WINDOW_SIZE: usize =
+        std::mem::size_of::<((&str, u32), RateWindow)>() + 2 * std::mem::size_of::<usize>();
+
+    /// How many records one callsite has logged in its current rate limit window.
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) struct RateWindow {
+        start: SystemTime,
+        count: u32,
+        suppressed: u32,
+    }
+
+    impl RateWindow {
+        pub(crate) fn new(start: SystemTime) -> Self {
+            RateWindow {
+                start,
+                count: 0,
+                suppressed: 0,
+            }
+        }
+
+        /// Admits a record at `now` unless `max` were already admitted in the current
+        /// window of length `per`. Returns how many were suppressed since the last one
+        /// admitted, or `None` to drop the record.
+        pub(crate) fn admit(&mut self, now: SystemTime, max: u32, per: Duration) -> Option<u32> {
+            if now.duration_since(self.start).map_or(true, |elapsed| elapsed >= per) {
+                self.start = now;
+                self.count = 0;
+            }
+            if self.count >= max {
+                self.suppressed += 1;
+                return None;
+            }
+            self.count += 1;
+            Some(std::mem::take(&mut self.suppressed))
+        }
+    }
+
+    /// One HTTP access record with consistently named fields, logged by `http_log!`.
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct HttpAccess<'a> {
+        pub method: &'a str,
+        pub path: &'a str,
+        pub status: u16,
+        pub latency_ms: f64,
+        pub bytes: u64,
+        /// Omitted from the output when empty.
+        pub remote_ip: &'a str,
+    }
+
+    impl Display for HttpAccess<'_> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(
+                f,
+                "method={} path={} status={} latency_ms={} bytes={}",
+                self.method, self.path, self.status, self.latency_ms, self.bytes
+            )?;
+            if !self.remote_ip.is_empty() {
+                write!(f, " remote_ip={}", self.remote_ip)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// A catalogued event with a stable, greppable code such as `E1042`.
+    #[derive(Debug, Clone)]
+    pub struct EventCode {
+        pub code: &'static str,
+        pub level: LogLevel,
+        /// Message with `{field}` placeholders filled from the `log_event!` fields.
+        pub template: &'static str,
+        pub docs_url: Option<&'static str>,
+    }
+
+    static EVENT_CODES: RwLock<Vec<EventCode>> = RwLock::new(Vec::new());
+
+    /// A snapshot of the logger's effective settings, from `Logger::settings()`.
+    ///
+    /// `Display` lists one `name = value` per line; `diff` shows what changed between two
+    /// snapshots, e.g. before and after start-up configuration.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct LoggerSettings {
+        entries: Vec<(&'static str, String)>,
+    }
+
+    impl LoggerSettings {
+        /// The value of one setting as displayed, e.g. `get("output")`.
+        pub fn get(&self, name: &str) -> Option<&str> {
+            self.entries
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.as_str())
+        }
+
+        /// One `name: old -> new` line per setting that differs in `other`.
+        pub fn diff(&self, other: &LoggerSettings) -> Vec<String> {
+            self.entries
+                .iter()
+                .zip(&other.entries)
+                .filter(|((_, old), (_, new))| old != new)
+                .map(|((name, old), (_, new))| format!("{name}: {old} -> {new}"))
+                .collect()
+        }
+    }
+
+    impl Display for LoggerSettings {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let width = self.entries.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+            for (name, value) in &self.entries {
+                writeln!(f, "{name:<width$} = {value}")?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Settings read from `FIRO_LOG_*` environment variables; `None` leaves a setting as is.
+    #[derive(Debug, Default, PartialEq)]
+    pub(crate) struct EnvConfig {
+        pub(crate) level: Option<LevelFilter>,
+        pub(crate) output: Option<Output>,
+        pub(crate) console_format: Option<OutputFormat>,
+        pub(crate) colours: Option<bool>,
+        pub(crate) utc_timestamps: Option<bool>,
+        pub(crate) timestamp_format: Option<TimestampFormat>,
+    }
+
+    impl EnvConfig {
+        /// Parses the variables returned by `lookup`, collecting an error for every
+        /// invalid value instead of stopping at the first one.
+        pub(crate) fn read(lookup: impl Fn(&str) -> Option<String>) -> (Self, Vec<LoggerError>) {
+            let mut errors = Vec::new();
+            let var = |name: &str| lookup(name).map(|value| (name.to_owned(), value));
+            let config = EnvConfig {
+                level: env_value(var("FIRO_LOG_LEVEL"), parse_level_filter, &mut errors),
+                output: env_value(var("FIRO_LOG_OUTPUT"), parse_output, &mut errors),
+                console_format: env_value(var("FIRO_LOG_FORMAT"), parse_console_format, &mut errors),
+                colours: env_value(var("FIRO_LOG_COLOURS"), parse_bool, &mut errors),
+                utc_timestamps: env_value(var("FIRO_LOG_UTC"), parse_bool, &mut errors),
+                timestamp_format: env_value(
+                    var("FIRO_LOG_TIMESTAMP"),
+                    parse_timestamp_format,
+                    &mut errors,
+                ),
+            };
+            (config, errors)
+        }
+
+        fn apply(self) {
+            if let Some(filter) = self.level {
+                Logger::set_level(filter);
+            }
+            if let Some(output) = self.output {
+                Logger::set_output(output);
+            }
+            if let Some(format) = self.console_format {
+                Logger::set_console_format(format);
+            }
+            if let Some(enabled) = self.colours {
+                Logger::set_colours(enabled);
+            }
+            if let Some(enabled) = self.utc_timestamps {
+                Logger::set_utc_timestamps(enabled);
+            }
+            if let Some(format) = self.timestamp_format {
+                Logger::set_timestamp_format(format);
+            }
+        }
+    }
+
+    /// Parses a `(name, value)` variable, recording an error if the value is invalid.
+    /// Parsers return the accepted values as their error.
+    fn env_value<T>(
+        variable: Option<(String, String)>,
+        parse: fn(&str) -> Result<T, &'static str>,
+        errors: &mut Vec<LoggerError>,
+    ) -> Option<T> {
+        let (setting, value) = variable?;
+        match parse(value.trim().to_ascii_lowercase().as_str()) {
+            Ok(parsed) => Some(parsed),
+            Err(expected) => {
+                errors.push(LoggerError::Config {
+                    setting,
+                    value,
+                    expected: expected.to_owned(),
+                });
+                None
+            }
+        }
+    }
+
+    fn parse_level_filter(value: &str) -> Result<LevelFilter, &'static str> {
+        match value {
+            "off" => Ok(LevelFilter::Off),
+            _ => LogLevel::from_name(&value.to_ascii_uppercase())
+                .map(LevelFilter::Min)
+                .ok_or("off, critical, error, warning, info, success, log or debug"),
+        }
+    }
+
+    fn parse_output(value: &str) -> Result<Output, &'static str> {
+        match value {
+            "console_and_file" => Ok(Output::ConsoleAndFile),
+            "console" => Ok(Output::Console),
+            "file" => Ok(Output::File),
+            "none" => Ok(Output::None),
+            _ => Err("console_and_file, console, file or none"),
+        }
+    }
+
+    fn parse_console_format(value: &str) -> Result<OutputFormat, &'static str> {
+        match value {
+            "text" => Ok(OutputFormat::Text),
+            "pretty" => Ok(OutputFormat::Pretty),
+            "columns" => Ok(OutputFormat::Columns(ColumnWidths::default())),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "syslog" => Ok(OutputFormat::Syslog(SyslogFacility::User)),
+            "pattern" => Ok(OutputFormat::Pattern),
+            "json" => Ok(OutputFormat::Json { pretty: false }),
+            "json-pretty" => Ok(OutputFormat::Json { pretty: true }),
+            _ => Err("text, pretty, columns, csv, tsv, syslog, pattern, json or json-pretty"),
+        }
+    }
+
+    fn parse_timestamp_format(value: &str) -> Result<TimestampFormat, &'static str> {
+        match value {
+            "datetime" => Ok(TimestampFormat::DateTime),
+            "datetime_nanos" => Ok(TimestampFormat::DateTimeNanos),
+            "unix_seconds" => Ok(TimestampFormat::UnixSeconds),
+            "unix_millis" => Ok(TimestampFormat::UnixMillis),
+            "unix_nanos" => Ok(TimestampFormat::UnixNanos),
+            _ => Err("datetime, datetime_nanos, unix_seconds, unix_millis or unix_nanos"),
+        }
+    }
+
+    fn parse_bool(value: &str) -> Result<bool, &'static str> {
+        match value {
+            "1" | "true" | "yes" | "on" => Ok(true),
+            "0" | "false" | "no" | "off" => Ok(false),
+            _ => Err("true or false"),
+        }
+    }
+
+    /// Held shared while a record is written, and exclusively while a `LogBatch` commits
+    /// or a log file is rewritten by `expire_records` or `privacy::forget_subject`.
+    pub(crate) static EMIT_LOCK: RwLock<()> = RwLock::new(());
+
+    /// Records for one operation (e.g. one request), written together on `commit` so
+    /// concurrent requests can't interleave with them. Dropping the batch discards them.
+    #[must_use = "the records are discarded unless the batch is committed"]
+    #[derive(Debug, Default)]
+    pub struct LogBatch {
+        records: Vec<LogRecord>,
+    }
+
+    impl LogBatch {
+        pub fn new() -> Self {
+            LogBatch::default()
+        }
+
+        /// Adds a record, stamped now, as the level functions would log it.
+        pub fn log(&mut self, level: LogLevel, message: Arguments) {
+            self.log_from(None, level, message);
+        }
+
+        /// Like `log`, with the caller's location.
+        pub fn log_from(
+            &mut self,
+            callsite: Option<Callsite>,
+            level: LogLevel,
+            message: Arguments,
+        ) {
+            if let Some(record) = Logger::build_record(level, callsite, message) {
+                self.records.push(record);
+            }
+        }
+
+        /// Adds an already built record.
+        pub fn push(&mut self, record: LogRecord) {
+            self.records.push(record);
+        }
+
+        pub fn len(&self) -> usize {
+            self.records.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.records.is_empty()
+        }
+
+        /// Writes all records, contiguously and in order.
+        pub fn commit(self) {
+            Logger::dispatch_batch(self.records);
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct Logger;
+
+    impl Logger {
+        #[cfg(test)]
+        pub(crate) fn format_message(level: LogLevel, message: &str) -> (String, String) {
+            let record = LogRecord::new(level, message);
+            (Self::format_text(&record, true), Self::format_text(&record, false))
+        }
+
+        /// The text layout; without colours this is also the log file line.
+        pub(crate) fn format_text(record: &LogRecord, colours: bool) -> String {
+            Self::format_text_with(record, colours, FieldStyle::default())
+        }
+
+        pub(crate) fn format_text_with(
+            record: &LogRecord,
+            colours: bool,
+            style: FieldStyle,
+        ) -> String {
+            let level = record.level;
+            let message = Self::styled_message(record, colours);
+
+            let date = Self::format_timestamp(record.timestamp);
+            let (fields, block) = match style.placement {
+                FieldPlacement::Layout | FieldPlacement::Inline => {
+                    (Self::inline_fields(&Self::styled_fields(record, style)), String::new())
+                }
+                FieldPlacement::Block => {
+                    (String::new(), Self::block_fields(&Self::styled_fields(record, style), 4, ""))
+                }
+                FieldPlacement::Omitted => (String::new(), String::new()),
+            };
+            let module = record.callsite.and_then(|callsite| {
+                let style = *MODULE_STYLE.read().unwrap();
+                shorten_module(callsite.module, style, STRIP_CRATE_NAME.load(Ordering::Relaxed))
+            });
+
+            let line = match (colours, module) {
+                (true, Some(module)) => {
+                    let colour_code = level.colour();
+                    format!("{date}{colour_code} [{}]\x1b[0m {module}: {message}{fields} ", level.as_str())
+                }
+                (true, None) => {
+                    let colour_code = level.colour();
+                    format!("{date}{colour_code} [{}]: \x1b[0m {message}{fields} ", level.as_str())
+                }
+                (false, Some(module)) => {
+                    format!("{date} [{}] {module}: {message}{fields} ", level.as_str())
+                }
+                (false, None) => format!("{date} [{}]: {message}{fields} ", level.as_str()),
+            };
+            line + &block
+        }
+
+        pub(crate) fn format_as(
+            record: &LogRecord,
+            format: OutputFormat,
+            colours: bool,
+            fields: FieldStyle,
+        ) -> String {
+            match format {
+                OutputFormat::Text => Self::format_text_with(record, colours, fields),
+                OutputFormat::Pretty => Self::format_pretty_with(record, colours, fields),
+                OutputFormat::Columns(widths) => Self::format_columns(record, colours, widths),
+                OutputFormat::Csv => Self::format_delimited(record, &Self::csv_columns(), ','),
+                OutputFormat::Tsv => Self::format_delimited(record, &Self::csv_columns(), '\t'),
+                OutputFormat::Syslog(facility) => Self::format_syslog(record, facility),
+                OutputFormat::Json { pretty } => Self::format_json(record, pretty),
+                OutputFormat::Pattern => {
+                    let pieces = PATTERN.read().unwrap();
+                    if pieces.is_empty() {
+                        Self::format_text(record, colours)
+                    } else {
+                        Self::format_pattern(record, colours, &pieces)
+                    }
+                }
+            }
+        }
+
+        /// `OutputFormat::Json`; `module`, `file` and `line` are left out for records
+        /// without a callsite.
+        pub(crate) fn format_json(record: &LogRecord, pretty: bool) -> String {
+            let severity_number = match *JSON_SEVERITY_SCALE.read().unwrap() {
+                SeverityScale::OpenTelemetry => record.level.otel_severity_number(),
+                SeverityScale::Syslog => record.level.syslog_severity(),
+            };
+            let mut members = vec![
+                ("timestamp", json_string(&Self::format_timestamp(record.timestamp))),
+                ("level", json_string(record.level.as_str())),
+                ("severity_number", severity_number.to_string()),
+            ];
+            if let Some(callsite) = record.callsite {
+                members.push(("module", json_string(callsite.module)));
+                members.push(("file", json_string(callsite.display_file())));
+                members.push(("line", callsite.line.to_string()));
+            }
+            members.push(("message", json_string(&Self::styled_message(record, false))));
+            let fields = Self::record_fields(record);
+            if !fields.is_empty() {
+                let mut object = String::from("{");
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        object.push(',');
+                    }
+                    if pretty {
+                        object.push_str("\n    ");
+                    }
+                    let colon = if pretty { ": " } else { ":" };
+                    let value = match record.field_unit(key) {
+                        Some(_) if value.parse::<u64>().is_ok() => value.to_string(),
+                        _ => json_string(value),
+                    };
+                    let _ = write!(object, "{}{colon}{value}", json_string(key));
+                }
+                object.push_str(if pretty { "\n  }" } else { "}" });
+                members.push(("fields", object));
+            }
+
+            let mut json = String::from("{");
+            for (index, (key, value)) in members.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                let _ = if pretty {
+                    write!(json, "\n  \"{key}\": {value}")
+                } else {
+                    write!(json, "\"{key}\":{value}")
+                };
+            }
+            json.push_str(if pretty { "\n}" } else { "}" });
+            json
+        }
+
+        /// Chooses the scale of the `severity_number` in JSON lines, so backends can
+        /// query level ranges numerically. Defaults to OpenTelemetry's.
+        pub fn set_json_severity_scale(scale: SeverityScale) {
+            *JSON_SEVERITY_SCALE.write().unwrap() = scale;
+        }
+
+        /// Sets the layout of `OutputFormat::Pattern`, in log4rs style:
+        ///
+        /// - `%d` timestamp per `Logger::set_timestamp_format`, or `%d{%H:%M:%S}` with any
+        ///   chrono `strftime` pattern (needs the `chrono` feature)
+        /// - `%l` level, `%m` (or `%m{target}`) module path, `%f` file, `%L` line
+        /// - `%t` thread name, `%msg` message, `%kv` fields as `key=value`, `%%` a `%`
+        ///
+        /// Until a pattern is set, `OutputFormat::Pattern` falls back to `Text`.
+        pub fn set_pattern(pattern: &str) -> Result<(), LoggerError> {
+            *PATTERN.write().unwrap() = parse_pattern(pattern)?;
+            Ok(())
+        }
+
+        pub(crate) fn format_pattern(
+            record: &LogRecord,
+            colours: bool,
+            pieces: &[PatternPiece],
+        ) -> String {
+            let mut line = String::new();
+            for piece in pieces {
+                match piece {
+                    PatternPiece::Literal(text) => line.push_str(text),
+                    PatternPiece::Timestamp(None) => {
+                        line.push_str(&Self::format_timestamp(record.timestamp));
+                    }
+                    #[cfg(feature = "chrono")]
+                    PatternPiece::Timestamp(Some(pattern)) => {
+                        let timestamp = record.timestamp;
+                        let _ = if UTC_TIMESTAMPS.load(Ordering::Relaxed) {
+                            write!(line, "{}", DateTime::<Utc>::from(timestamp).format(pattern))
+                        } else {
+                            write!(line, "{}", DateTime::<Local>::from(timestamp).format(pattern))
+                        };
+                    }
+                    #[cfg(not(feature = "chrono"))]
+                    PatternPiece::Timestamp(Some(_)) => {
+                        line.push_str(&Self::format_timestamp(record.timestamp));
+                    }
+                    PatternPiece::Level if colours => {
+                        let level = record.level;
+                        let _ = write!(line, "{}{}\x1b[0m", level.colour(), level.as_str());
+                    }
+                    PatternPiece::Level => line.push_str(record.level.as_str()),
+                    PatternPiece::Module => {
+                        line.push_str(record.callsite.map_or("", |callsite| callsite.module));
+                    }
+                    PatternPiece::File => {
+                        let file = record.callsite.map(|callsite| callsite.display_file());
+                        line.push_str(file.unwrap_or(""));
+                    }
+                    PatternPiece::Line => {
+                        if let Some(callsite) = record.callsite {
+                            let _ = write!(line, "{}", callsite.line);
+                        }
+                    }
+                    PatternPiece::Thread => {
+                        line.push_str(thread::current().name().unwrap_or("unnamed"));
+                    }
+                    PatternPiece::Message => line.push_str(&Self::styled_message(record, colours)),
+                    PatternPiece::Fields => {
+                        line.push_str(Self::format_fields(record).trim_start());
+                    }
+                }
+            }
+            line
+        }
+
+        /// An RFC 5424 line. Fields become parameters of one `firo@32473` structured data
+        /// element (32473 being the enterprise number reserved for examples), with
+        /// Success records getting `outcome="success"` per `Logger::set_success_mapping`.
+        pub(crate) fn format_syslog(record: &LogRecord, facility: SyslogFacility) -> String {
+            let app = Self::script_name();
+            Self::format_syslog_as(record, facility, if app.is_empty() { "-" } else { &app })
+        }
+
+        /// The RFC 5424 line of `format_syslog`, sent as the program `app`.
+        pub(crate) fn format_syslog_as(
+            record: &LogRecord,
+            facility: SyslogFacility,
+            app: &str,
+        ) -> String {
+            let severity = record.level.syslog_severity();
+            let priority = u16::from(facility.code()) * 8 + u16::from(severity);
+            let since_epoch = record.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+            let timestamp = format!(
+                "{}.{:06}Z",
+                format_utc(record.timestamp).replacen(' ', "T", 1),
+                since_epoch.subsec_micros()
+            );
+
+            let mut fields = Self::record_fields(record);
+            if record.level == LogLevel::Success && Self::success_mapping().outcome_field {
+                fields.push(("outcome", Cow::Borrowed("success")));
+            }
+            let mut structured = String::new();
+            if fields.is_empty() {
+                structured.push('-');
+            } else {
+                structured.push_str("[firo@32473");
+                for (key, value) in fields {
+                    let name: String = key
+                        .chars()
+                        .map(|c| match c {
+                            '=' | ' ' | ']' | '"' => '_',
+                            c if c.is_ascii_graphic() => c,
+                            _ => '_',
+                        })
+                        .take(32)
+                        .collect();
+                    let _ = write!(structured, " {name}=\"");
+                    for c in value.chars() {
+                        if matches!(c, '"' | '\\' | ']') {
+                            structured.push('\\');
+                        }
+                        structured.push(c);
+                    }
+                    structured.push('"');
+                }
+                structured.push(']');
+            }
+
+            format!(
+                "<{priority}>1 {timestamp} {} {app} {} - {structured} {}",
+                syslog_hostname(),
+                std::process::id(),
+                Self::styled_message(record, false)
+            )
+        }
+
+        fn csv_columns() -> Vec<CsvColumn> {
+            let columns = CSV_COLUMNS.read().unwrap();
+            if columns.is_empty() {
+                vec![
+                    CsvColumn::Timestamp,
+                    CsvColumn::Level,
+                    CsvColumn::Module,
+                    CsvColumn::Message,
+                ]
+            } else {
+                columns.clone()
+            }
+        }
+
+        /// One delimited line with `columns` of `record`; the message includes no fields
+        /// other than those selected as columns.
+        pub(crate) fn format_delimited(
+            record: &LogRecord,
+            columns: &[CsvColumn],
+            delimiter: char,
+        ) -> String {
+            let values = columns.iter().map(|column| match column {
+                CsvColumn::Timestamp => Cow::Owned(Self::format_timestamp(record.timestamp)),
+                CsvColumn::Level => Cow::Borrowed(record.level.as_str()),
+                CsvColumn::Module => {
+                    Cow::Borrowed(record.callsite.map_or("", |callsite| callsite.module))
+                }
+                CsvColumn::Message => Self::styled_message(record, false),
+                CsvColumn::Field(key) => Cow::Borrowed(record.field(key).unwrap_or_default()),
+            });
+            join_delimited(values, delimiter)
+        }
+
+        /// The header row for `columns`.
+        pub(crate) fn delimited_header(columns: &[CsvColumn], delimiter: char) -> String {
+            let names = columns.iter().map(|column| match column {
+                CsvColumn::Timestamp => Cow::Borrowed("timestamp"),
+                CsvColumn::Level => Cow::Borrowed("level"),
+                CsvColumn::Module => Cow::Borrowed("module"),
+                CsvColumn::Message => Cow::Borrowed("message"),
+                CsvColumn::Field(key) => Cow::Borrowed(key.as_str()),
+            });
+            join_delimited(names, delimiter)
+        }
+
+        /// Fixed-width `time level module message` layout. The module uses the configured
+        /// `ModuleStyle`, or the full path while that is `Hidden`.
+        pub(crate) fn format_columns(
+            record: &LogRecord,
+            colours: bool,
+            widths: ColumnWidths,
+        ) -> String {
+            let date = Self::format_timestamp(record.timestamp);
+            let style = match *MODULE_STYLE.read().unwrap() {
+                ModuleStyle::Hidden => ModuleStyle::Full,
+                style => style,
+            };
+            let strip_crate = STRIP_CRATE_NAME.load(Ordering::Relaxed);
+            let module = record
+                .callsite
+                .and_then(|callsite| shorten_module(callsite.module, style, strip_crate))
+                .unwrap_or_default();
+            let module = fit_column(&module, widths.module);
+            let (colour_code, reset) = if colours {
+                (record.level.colour(), "\x1b[0m")
+            } else {
+                ("", "")
+            };
+            format!(
+                "{date} {colour_code}{:<level_width$}{reset} {module:<module_width$} {}{}",
+                record.level.as_str(),
+                Self::styled_message(record, colours),
+                Self::format_fields(record),
+                level_width = widths.level,
+                module_width = widths.module,
+            )
+        }
+
+        /// The message with any markup rendered as ANSI codes, or stripped without colours.
+        fn styled_message(record: &LogRecord, colours: bool) -> Cow<'_, str> {
+            if MARKUP_ENABLED.load(Ordering::Relaxed) {
+                render_markup(&record.message, colours)
+            } else {
+                Cow::Borrowed(&record.message)
+            }
+        }
+
+        /// Development-friendly console line: time since the first record, a level badge,
+        /// the message with a dim source location, and fields in an aligned block below.
+        #[cfg(any(test, feature = "bench"))]
+        pub(crate) fn format_pretty(record: &LogRecord, colours: bool) -> String {
+            Self::format_pretty_with(record, colours, FieldStyle::default())
+        }
+
+        pub(crate) fn format_pretty_with(
+            record: &LogRecord,
+            colours: bool,
+            style: FieldStyle,
+        ) -> String {
+            let (dim, bold, reset) = if colours {
+                ("\x1b[2m", "\x1b[1m", "\x1b[0m")
+            } else {
+                ("", "", "")
+            };
+            let colour_code = if colours { record.level.colour() } else { "" };
+
+            let start = *PRETTY_START.get_or_init(|| record.timestamp);
+            let elapsed = record.timestamp.duration_since(start).unwrap_or_default();
+
+            let mut line = format!(
+                "{dim}{:>9}{reset} {bold}{colour_code}{:<7}{reset} {}",
+                format!("+{:.3}s", elapsed.as_secs_f64()),
+                record.level.as_str(),
+                Self::styled_message(record, colours)
+            );
+            if let Some(callsite) = record.callsite {
+                line.push_str(&format!(" {dim}{}:{}{reset}", callsite.display_file(), callsite.line));
+            }
+
+            match style.placement {
+                FieldPlacement::Layout | FieldPlacement::Block => {
+                    let fields = Self::styled_fields(record, style);
+                    line.push_str(&Self::block_fields(&fields, 18, dim));
+                }
+                FieldPlacement::Inline => {
+                    line.push_str(&Self::inline_fields(&Self::styled_fields(record, style)));
+                }
+                FieldPlacement::Omitted => {}
+            }
+            line
+        }
+
+        /// `record_fields`, ordered by key when `style` asks for it.
+        fn styled_fields(record: &LogRecord, style: FieldStyle) -> Vec<(&str, Cow<'_, str>)> {
+            let mut fields = Self::humanized_fields(record);
+            if style.sorted {
+                fields.sort_by_key(|(key, _)| *key);
+            }
+            fields
+        }
+
+        fn inline_fields(fields: &[(&str, Cow<'_, str>)]) -> String {
+            let mut line = String::new();
+            for (key, value) in fields {
+                let _ = write!(line, " {key}={value}");
+            }
+            line
+        }
+
+        /// One `key = value` line per field, indented by `indent` with the keys aligned;
+        /// `dim` (and a reset) wraps each key when it isn't empty.
+        fn block_fields(fields: &[(&str, Cow<'_, str>)], indent: usize, dim: &str) -> String {
+            let reset = if dim.is_empty() { "" } else { "\x1b[0m" };
+            let width = fields.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+            let mut block = String::new();
+            for (key, value) in fields {
+                let _ = write!(block, "\n{:>indent$}{dim}{key:<width$}{reset} = {value}", "");
+            }
+            block
+        }
+
+        /// The optional per-record fields, in display order.
+        /// Values are borrowed from the record; only the sequence number is rendered.
+        pub(crate) fn record_fields(record: &LogRecord) -> Vec<(&str, Cow<'_, str>)> {
+            let mut fields = Vec::new();
+            if let Some(event_id) = &record.event_id {
+                fields.push(("event_id", Cow::Borrowed(event_id.as_str())));
+            }
+            if let Some(sequence) = record.sequence {
+                fields.push(("seq", Cow::Owned(sequence.to_string())));
+            }
+            if let Some(tenant_id) = &record.tenant_id {
+                fields.push(("tenant_id", Cow::Borrowed(tenant_id.as_str())));
+            }
+            let shared = record.shared_metadata.iter().flat_map(|shared| shared.iter());
+            for (key, value) in record.metadata.iter().chain(shared) {
+                fields.push((key.as_str(), Cow::Borrowed(value.as_str())));
+            }
+            fields
+        }
+
+        /// `record_fields` with measures (see `LogRecord::field_units`) humanized.
+        pub(crate) fn humanized_fields(record: &LogRecord) -> Vec<(&str, Cow<'_, str>)> {
+            let mut fields = Self::record_fields(record);
+            if !record.field_units.is_empty() {
+                for (key, value) in &mut fields {
+                    let number = value.parse::<u64>().ok();
+                    if let (Some(unit), Some(number)) = (record.field_unit(key), number) {
+                        *value = Cow::Owned(unit.humanize(number));
+                    }
+                }
+            }
+            fields
+        }
+
+        /// Renders the optional per-record fields as ` key=value` pairs.
+        pub(crate) fn format_fields(record: &LogRecord) -> String {
+            Self::inline_fields(&Self::humanized_fields(record))
+        }
+
+        pub(crate) fn format_timestamp(timestamp: SystemTime) -> String {
+            Self::format_timestamp_as(timestamp, *TIMESTAMP_FORMAT.read().unwrap())
+        }
+
+        pub(crate) fn format_timestamp_as(timestamp: SystemTime, format: TimestampFormat) -> String {
+            let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+            match format {
+                TimestampFormat::UnixSeconds => return since_epoch.as_secs().to_string(),
+                TimestampFormat::UnixMillis => return since_epoch.as_millis().to_string(),
+                TimestampFormat::UnixNanos => return since_epoch.as_nanos().to_string(),
+                TimestampFormat::DateTime | TimestampFormat::DateTimeNanos => {}
+            }
+
+            #[cfg(feature = "chrono")]
+            let mut date = {
+                let custom = DATETIME_FORMAT.read().unwrap();
+                match (custom.as_deref(), UTC_TIMESTAMPS.load(Ordering::Relaxed)) {
+                    (None, true) => format_utc(timestamp),
+                    (Some(pattern), true) => {
+                        DateTime::<Utc>::from(timestamp).format(pattern).to_string()
+                    }
+                    (pattern, false) => DateTime::<Local>::from(timestamp)
+                        .format(pattern.unwrap_or(DATETIME_PATTERN))
+                        .to_string(),
+                }
+            };
+            #[cfg(not(feature = "chrono"))]
+            let mut date = format_utc(timestamp);
+
+            if format == TimestampFormat::DateTimeNanos {
+                date.push_str(&format!(".{:09}", since_epoch.subsec_nanos()));
+            }
+            date
+        }
+
+        /// The program name without directory or `.exe`, used as the log file stem.
+        pub(crate) fn script_name() -> String {
+            let script_name = env::args()
+                .next()
+                .map(|arg| {
+                    arg.split('/')
+                        .next_back()
+                        .unwrap_or(arg.as_str())
+                        .split('\\')
+                        .next_back()
+                        .unwrap_or(arg.as_str())
+                        .to_owned()
+                })
+                .unwrap_or("unknown".to_owned());
+
+            if script_name.ends_with(".exe") {
+                return script_name.replace(".exe", "");
+            }
+            script_name
+        }
+
+        pub(crate) fn log_file_name(per_thread: bool, tenant: Option<&str>) -> String {
+            let mut script_name = Self::script_name();
+            if let Some(session) = SESSION.read().unwrap().as_deref() {
+                script_name.push_str(session);
+            }
+
+            if let Some(tenant) = tenant {
+                let tenant: String = tenant
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+                    .collect();
+                script_name = format!("{script_name}.{tenant}");
+            }
+            if per_thread {
+                let thread = thread::current();
+                let thread_name = match thread.name() {
+                    Some(name) => name.replace(['/', '\\'], "_"),
+                    None => {
+                        let id: String = format!("{:?}", thread.id())
+                            .chars()
+                            .filter(char::is_ascii_digit)
+                            .collect();
+                        format!("thread-{id}")
+                    }
+                };
+                return format!("{script_name}.{thread_name}.log");
+            }
+            format!("{}.log", script_name)
+        }
+
+        /// The directory log files are written to: the working directory unless
+        /// `set_log_dir` was called.
+        fn log_dir() -> PathBuf {
+            LOG_DIR.read().unwrap().clone().unwrap_or_else(|| PathBuf::from("."))
+        }
+
+        pub(crate) fn log_file_path(per_thread: bool, tenant: Option<&str>) -> PathBuf {
+            let name = Self::log_file_name(per_thread, tenant);
+            match LOG_DIR.read().unwrap().as_ref() {
+                Some(dir) => dir.join(name),
+                None => PathBuf::from(name),
+            }
+        }
+
+        fn file_log(record: &LogRecord, message: &str) -> Result<(), LoggerError> {
+            let per_thread = PER_THREAD_FILES.load(Ordering::Relaxed);
+            let tenant = if PER_TENANT_FILES.load(Ordering::Relaxed) {
+                record.tenant_id.as_deref()
+            } else {
+                None
+            };
+            let path = Self::log_file_path(per_thread, tenant);
+            let path = path.as_path();
+            let delimiter = match *FILE_FORMAT.read().unwrap() {
+                OutputFormat::Csv => Some(','),
+                OutputFormat::Tsv => Some('\t'),
+                _ => None,
+            };
+            if let Some(delimiter) = delimiter {
+                if path.metadata().map_or(true, |metadata| metadata.len() == 0) {
+                    let header = Self::delimited_header(&Self::csv_columns(), delimiter);
+                    Self::append_line(path, &header)?;
+                }
+            }
+            Self::append_line(path, message)?;
+            Self::check_disk_budget(path);
+            Ok(())
+        }
+
+        /// Enforces the disk budget at most once a second, never deleting `active`.
+        fn check_disk_budget(active: &Path) {
+            let budget = DISK_BUDGET.load(Ordering::Relaxed);
+            if budget == 0 {
+                return;
+            }
+            let start = PROCESS_START.get_or_init(std::time::Instant::now);
+            let now = start.elapsed().as_millis() as u64 + 1_000;
+            let last = LAST_BUDGET_CHECK.load(Ordering::Relaxed);
+            if now - last < 1_000
+                || LAST_BUDGET_CHECK
+                    .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_err()
+            {
+                return;
+            }
+            let dir = Self::log_dir();
+            if let Err(source) = enforce_disk_budget(&dir, &Self::script_name(), budget, active) {
+                Self::report_error(LoggerError::FileWrite { path: dir, source });
+            }
+        }
+
+        fn append_line(path: &Path, message: &str) -> Result<(), LoggerError> {
+            let write = || -> io::Result<()> {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                file.write_all(message.as_bytes())?;
+                file.write_all(b"\n")
+            };
+            write().map_err(|source| LoggerError::FileWrite {
+                path: path.to_owned(),
+                source,
+            })
+        }
+
+        /// Prints a console line, turning `stream` off for good once it is closed.
+        fn console_write(line: &str, stream: Stream) {
+            let result = match stream {
+                Stream::Stdout => writeln!(io::stdout().lock(), "{line}"),
+                Stream::Stderr => writeln!(io::stderr().lock(), "{line}"),
+            };
+            if let Err(err) = result {
+                if err.kind() == io::ErrorKind::BrokenPipe {
+                    stream.closed_flag().store(true, Ordering::Relaxed);
+                    Self::report_error(LoggerError::ConsoleClosed(err));
+                } else {
+                    Self::report_error(LoggerError::ConsoleWrite(err));
+                }
+            }
+        }
+
+        pub(crate) fn report_error(error: LoggerError) {
+            let error = EMIT_ERRORS.with(|errors| match errors.borrow_mut().as_mut() {
+                Some(errors) => {
+                    errors.push(error);
+                    None
+                }
+                None => Some(error),
+            });
+            let Some(error) = error else {
+                return;
+            };
+            match ERROR_HANDLER.read().unwrap().as_ref() {
+                Some(handler) => handler(&error),
+                None => {
+                    let _ = writeln!(io::stderr(), "firo_logger: {error}");
+                }
+            }
+        }
+
+        /// Reports each conflict between the current settings that hasn't been reported yet.
+        fn check_conflicts() {
+            let conflicts = config_conflicts(
+                *OUTPUT.read().unwrap(),
+                PER_THREAD_FILES.load(Ordering::Relaxed) || PER_TENANT_FILES.load(Ordering::Relaxed),
+                !ROUTES.read().unwrap().is_empty(),
+                CONSOLE_HOOK.read().unwrap().is_some(),
+            );
+            for conflict in conflicts {
+                Self::report_conflict(conflict);
+            }
+        }
+
+        fn report_conflict(conflict: &'static str) {
+            let mut reported = REPORTED_CONFLICTS.lock().unwrap();
+            if !reported.contains(&conflict) {
+                reported.push(conflict);
+                drop(reported);
+                Self::report_error(LoggerError::Conflict(conflict));
+            }
+        }
+
+        /// Routes internal failures (closed console, unwritable files) to `handler`.
+        pub fn set_error_handler<F>(handler: F)
+        where
+            F: Fn(&LoggerError) + Send + Sync + 'static,
+        {
+            *ERROR_HANDLER.write().unwrap() = Some(Box::new(handler));
+        }
+
+        /// Goes back to printing internal failures to stderr.
+        pub fn clear_error_handler() {
+            *ERROR_HANDLER.write().unwrap() = None;
+        }
+
+        /// Whether console output to stdout was disabled because stdout was closed.
+        pub fn console_closed() -> bool {
+            STDOUT_CLOSED.load(Ordering::Relaxed)
+        }
+
+        /// Prints console lines for `level` to `stream`.
+        pub fn set_level_stream(level: LogLevel, stream: Stream) {
+            let mut streams = LEVEL_STREAMS.write().unwrap();
+            streams.retain(|(existing, _)| *existing != level);
+            streams.push((level, stream));
+        }
+
+        /// Prints every level to `stream`, e.g. `Stream::Stderr` for pipeline-friendly CLIs.
+        /// Covers custom levels too, including ones registered afterwards, and replaces
+        /// the `set_level_stream` overrides made so far.
+        pub fn set_all_streams(stream: Stream) {
+            LEVEL_STREAMS.write().unwrap().clear();
+            *DEFAULT_STREAM.write().unwrap() = stream;
+        }
+
+        pub(crate) fn stream_for(level: LogLevel) -> Stream {
+            LEVEL_STREAMS
+                .read()
+                .unwrap()
+                .iter()
+                .find(|(existing, _)| *existing == level)
+                .map_or_else(|| *DEFAULT_STREAM.read().unwrap(), |(_, stream)| *stream)
+        }
+
+        /// Installs the clock used to timestamp new records.
+        pub fn set_clock<C: Clock + 'static>(clock: C) {
+            *CLOCK.write().unwrap() = Some(Box::new(clock));
+        }
+
+        /// Goes back to timestamping records with the system clock.
+        pub fn reset_clock() {
+            *CLOCK.write().unwrap() = None;
+        }
+
+        fn now() -> SystemTime {
+            match CLOCK.read().unwrap().as_ref() {
+                Some(clock) => clock.now(),
+                None => SystemClock.now(),
+            }
+        }
+
+        /// Sets the level filter for records whose module has no override.
+        /// Everything passes by default.
+        pub fn set_level(filter: LevelFilter) {
+            *LEVEL.write().unwrap() = filter;
+        }
+
+        /// Overrides the level filter for modules matching `pattern` (a glob such as
+        /// `hyper::*`). `LevelFilter::Off` silences a chatty module completely. When several
+        /// patterns match, the longest one wins.
+        pub fn set_module_level(pattern: &str, filter: LevelFilter) {
+            let mut levels = MODULE_LEVELS.write().unwrap();
+            levels.retain(|(existing, _)| existing != pattern);
+            levels.push((pattern.to_owned(), filter));
+        }
+
+        /// Removes all per-module level overrides.
+        pub fn clear_module_levels() {
+            MODULE_LEVELS.write().unwrap().clear();
+        }
+
+        /// Adds `fields` to every record logged from a module matching `pattern` (a glob
+        /// such as `payments::*`), e.g. `domain=payments` for routing. Every matching
+        /// rule applies, in the order they were added. Matching happens once per module;
+        /// records then share the cached fields.
+        pub fn add_module_fields(pattern: &str, fields: &[(&str, &str)]) {
+            let fields: SharedFields = fields
+                .iter()
+                .map(|(key, value)| ((*key).to_owned(), (*value).to_owned()))
+                .collect();
+            MODULE_FIELDS.write().unwrap().push((pattern.to_owned(), fields));
+            MODULE_FIELD_CACHE.write().unwrap().clear();
+        }
+
+        /// Removes all per-module field rules.
+        pub fn clear_module_fields() {
+            MODULE_FIELDS.write().unwrap().clear();
+            MODULE_FIELD_CACHE.write().unwrap().clear();
+        }
+
+        /// The merged `add_module_fields` rules matching `module`, cached per module.
+        fn module_fields(module: &'static str) -> Option<SharedFields> {
+            if let Some(cached) = MODULE_FIELD_CACHE.read().unwrap().get(module) {
+                return cached.clone();
+            }
+            let rules = MODULE_FIELDS.read().unwrap();
+            let mut matching = rules
+                .iter()
+                .filter(|(pattern, _)| glob_match(pattern, module))
+                .peekable();
+            let fields = matching.peek().is_some().then(|| {
+                matching
+                    .flat_map(|(_, fields)| fields.iter().cloned())
+                    .collect::<SharedFields>()
+            });
+            MODULE_FIELD_CACHE
+                .write()
+                .unwrap()
+                .insert(module, fields.clone());
+            fields
+        }
+
+        /// Drops records below `level` for `duration`, e.g. during a noisy maintenance
+        /// window, then reverts on a background thread. Info marker records are logged
+        /// when the quiet period starts and ends. A new call replaces a running one.
+        pub fn suppress_below(level: LogLevel, duration: Duration) -> io::Result<()> {
+            let generation = QUIET_GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+            Self::quiet_marker(format!(
+                "quiet period started: suppressing records below {} for {duration:?}",
+                level.as_str()
+            ));
+            *QUIET_PERIOD.write().unwrap() = Some(level);
+            thread::Builder::new()
+                .name("firo-quiet-period".to_string())
+                .spawn(move || {
+                    thread::sleep(duration);
+                    if QUIET_GENERATION.load(Ordering::Relaxed) == generation {
+                        Self::end_quiet_period();
+                    }
+                })?;
+            Ok(())
+        }
+
+        /// Ends the current quiet period early. Does nothing if none is active.
+        pub fn end_quiet_period() {
+            QUIET_GENERATION.fetch_add(1, Ordering::Relaxed);
+            if QUIET_PERIOD.write().unwrap().take().is_some() {
+                Self::quiet_marker("quiet period ended".to_string());
+            }
+        }
+
+        /// Markers skip the level filters, which the quiet period itself is part of.
+        fn quiet_marker(message: String) {
+            Self::dispatch(LogRecord::new(LogLevel::Info, message));
+        }
+
+        /// Whether a record at `level` from `module` passes the level filters. Checked
+        /// before the message is rendered.
+        pub(crate) fn level_enabled(level: LogLevel, module: Option<&str>) -> bool {
+            let module_levels = MODULE_LEVELS.read().unwrap();
+            let filter = module
+                .and_then(|module| {
+                    module_levels
+                        .iter()
+                        .filter(|(pattern, _)| glob_match(pattern, module))
+                        .max_by_key(|(pattern, _)| pattern.len())
+                        .map(|(_, filter)| *filter)
+                })
+                .unwrap_or_else(|| *LEVEL.read().unwrap());
+            let quiet = *QUIET_PERIOD.read().unwrap();
+            filter.allows(level) && quiet.is_none_or(|min| level.severity() >= min.severity())
+        }
+
+        /// Registers a filter that is evaluated for every record before it is formatted.
+        pub fn add_filter<F>(filter: F)
+        where
+            F: Fn(&LogRecord) -> bool + Send + Sync + 'static,
+        {
+            FILTERS.write().unwrap().push(Box::new(filter));
+        }
+
+        /// Removes all registered filters.
+        pub fn clear_filters() {
+            FILTERS.write().unwrap().clear();
+        }
+
+        /// Keeps records at or below `up_to` (e.g. Debug) only for a stable `ratio` of the
+        /// values of field `key`, so full detail exists for, say, 1% of `user_id`s:
+        /// `Logger::add_keyed_sampling("user_id", 0.01, LogLevel::Debug)`.
+        ///
+        /// The same value is always kept or always dropped, in every process. Records
+        /// without the field, or above `up_to`, are not affected. `key` may also be
+        /// `tenant_id`. Registered as a filter, so `clear_filters` removes it.
+        pub fn add_keyed_sampling(key: &str, ratio: f64, up_to: LogLevel) {
+            let key = key.to_owned();
+            Self::add_filter(move |record| {
+                if record.level.severity() > up_to.severity() {
+                    return true;
+                }
+                let value = match key.as_str() {
+                    "tenant_id" => record.tenant_id.as_deref(),
+                    key => record.field(key),
+                };
+                value.is_none_or(|value| key_sampled(value, ratio))
+            });
+        }
+
+        /// Silences the log statement at `file:line`, e.g. `("src/handlers/user.rs", 42)`.
+        /// `file` may be the path as compiled or as displayed after `set_source_prefix`.
+        /// Stays in effect until `enable_callsite` is called for the same location.
+        pub fn disable_callsite(file: &str, line: u32) {
+            let mut disabled = DISABLED_CALLSITES.write().unwrap();
+            if !disabled.iter().any(|(f, l)| f == file && *l == line) {
+                disabled.push((file.to_owned(), line));
+            }
+        }
+
+        /// Re-enables a log statement silenced with `disable_callsite`.
+        pub fn enable_callsite(file: &str, line: u32) {
+            DISABLED_CALLSITES
+                .write()
+                .unwrap()
+                .retain(|(f, l)| !(f == file && *l == line));
+        }
+
+        /// The locations currently silenced with `disable_callsite`.
+        pub fn disabled_callsites() -> Vec<(String, u32)> {
+            DISABLED_CALLSITES.read().unwrap().clone()
+        }
+
+        fn callsite_enabled(callsite: &Callsite) -> bool {
+            let disabled = DISABLED_CALLSITES.read().unwrap();
+            disabled.is_empty()
+                || !disabled.iter().any(|(file, line)| {
+                    *line == callsite.line && (file == callsite.file || file == callsite.display_file())
+                })
+        }
+
+        pub(crate) fn passes_filters(record: &LogRecord) -> bool {
+            FILTERS.read().unwrap().iter().all(|filter| filter(record))
+        }
+
+        /// Whether any error has been logged since start-up (or the last reset).
+        pub fn had_errors() -> bool {
+            ERROR_COUNT.load(Ordering::Relaxed) > 0
+        }
+
+        /// Whether any warning has been logged since start-up (or the last reset).
+        pub fn had_warnings() -> bool {
+            WARNING_COUNT.load(Ordering::Relaxed) > 0
+        }
+
+        /// Process exit code for CLIs: `1` if any error was logged, `0` otherwise.
+        ///
+        /// ```no_run
+        /// use firo_logger::logger::Logger;
+        ///
+        /// std::process::exit(Logger::exit_code_from_logs());
+        /// ```
+        pub fn exit_code_from_logs() -> i32 {
+            i32::from(Self::had_errors())
+        }
+
+        /// Clears the error and warning counters.
+        pub fn reset_severity_counts() {
+            ERROR_COUNT.store(0, Ordering::Relaxed);
+            WARNING_COUNT.store(0, Ordering::Relaxed);
+        }
+
+        /// Counts of the records that passed the filters since start-up.
+        pub fn stats() -> LoggerStats {
+            let by_level = LEVEL_COUNTS.lock().unwrap().clone();
+            let last_error_at = match LAST_ERROR_AT.load(Ordering::Relaxed) {
+                0 => None,
+                millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+            };
+            LoggerStats {
+                taken_at: Self::now(),
+                records: by_level.iter().map(|(_, count)| count).sum(),
+                errors: ERROR_COUNT.load(Ordering::Relaxed),
+                warnings: WARNING_COUNT.load(Ordering::Relaxed),
+                last_error_at,
+                clock_corrections: CLOCK_CORRECTIONS.load(Ordering::Relaxed),
+                memory_in_use: MEMORY_IN_USE.load(Ordering::Relaxed),
+                memory_dropped: MEMORY_DROPPED.load(Ordering::Relaxed),
+                by_level,
+            }
+        }
+
+        /// Caps the bytes held by the logger's buffers: records waiting in the `try_log`
+        /// queue, lines the TCP writer queues while disconnected, and rate limit windows.
+        /// Over the budget, `try_log` returns `WouldBlock`, the TCP writer drops the line
+        /// and new callsites go without rate limiting. Usage and drops are in
+        /// `Logger::stats`. `None` (the default) removes the cap; usage is still counted.
+        pub fn set_memory_budget(max_bytes: Option<usize>) {
+            MEMORY_BUDGET.store(max_bytes.unwrap_or(0), Ordering::Relaxed);
+        }
+
+        /// `Logger::stats()` as JSON, for health endpoints.
+        pub fn stats_json() -> String {
+            Self::stats().to_json()
+        }
+
+        /// Registers an enrichment provider whose fields are added to every record.
+        ///
+        /// The provider runs once now and then every `interval` on a background thread,
+        /// so expensive lookups (config version, feature flags, IP address) are not paid
+        /// per log call.
+        pub fn add_enricher<F>(interval: Duration, provider: F)
+        where
+            F: Fn() -> Vec<(String, String)> + Send + 'static,
+        {
+            ENRICHERS
+                .write()
+                .unwrap()
+                .push(Enricher::spawn(interval, provider));
+        }
+
+        /// Removes all enrichment providers and stops their refresh threads.
+        pub fn clear_enrichers() {
+            ENRICHERS.write().unwrap().clear();
+        }
+
+        /// Adds a route that copies matching records into another file.
+        pub fn add_route(route: Route) {
+            ROUTES.write().unwrap().push(route);
+            Self::check_conflicts();
+        }
+
+        /// Removes all routes.
+        pub fn clear_routes() {
+            ROUTES.write().unwrap().clear();
+        }
+
+        /// Logs an access record: `Error` for 5xx, `Warning` for 4xx, `Info` otherwise.
+        pub fn log_http(callsite: Callsite, access: &HttpAccess) {
+            let level = match access.status {
+                500.. => LogLevel::Error,
+                400..=499 => LogLevel::Warning,
+                _ => LogLevel::Info,
+            };
+            Self::log_msg(level, Some(callsite), format_args!("{access}"));
+        }
+
+        /// Adds a level named `name` (e.g. "AUDIT") that ranks with the built-in levels of
+        /// the same `severity` (Debug 0, Log 1, Info 2, Warning 3, Error 4, Critical 5) in
+        /// level filters and interop formats, and is shown in `colour`. Registering a name
+        /// again updates it and returns the same level. Past 65536 names the error handler
+        /// is told and Info is returned.
+        pub fn register_level(name: &'static str, severity: u8, colour: &'static str) -> LogLevel {
+            let level = CustomLevel {
+                name,
+                severity,
+                colour,
+            };
+            let mut levels = CUSTOM_LEVELS.write().unwrap();
+            let index = match levels.iter().position(|existing| existing.name == name) {
+                Some(index) => {
+                    levels[index] = level;
+                    index
+                }
+                None => levels.len(),
+            };
+            let Ok(custom) = u16::try_from(index) else {
+                drop(levels);
+                Self::report_error(LoggerError::Config {
+                    setting: "level".to_string(),
+                    value: name.to_string(),
+                    expected: format!("at most {} custom levels", u32::from(u16::MAX) + 1),
+                });
+                return LogLevel::Info;
+            };
+            if index == levels.len() {
+                levels.push(level);
+            }
+            LogLevel::Custom(custom)
+        }
+
+        pub(crate) fn custom_level_names() -> Vec<&'static str> {
+            CUSTOM_LEVELS.read().unwrap().iter().map(|level| level.name).collect()
+        }
+
+        /// Logs at the level registered as `name`; used by `log_custom!`. Unknown names are
+        /// reported to the error handler and logged as Info.
+        pub fn log_custom(callsite: Callsite, name: &str, args: Arguments) {
+            let level = LogLevel::from_name(name).unwrap_or_else(|| {
+                Self::report_error(LoggerError::Config {
+                    setting: "level".to_string(),
+                    value: name.to_string(),
+                    expected: "a level registered with Logger::register_level".to_string(),
+                });
+                LogLevel::Info
+            });
+            Self::log_msg(level, Some(callsite), args);
+        }
+
+        /// Logs a Warning the first time `feature` is reported, with `deprecated.feature`,
+        /// `deprecated.since` and (when not empty) `deprecated.note` fields; used by
+        /// `warn_deprecated!`.
+        pub fn warn_deprecated(callsite: Callsite, feature: &str, since: &str, note: &str) {
+            {
+                let mut warned = WARNED_DEPRECATIONS.lock().unwrap();
+                if warned.iter().any(|warned| warned == feature) {
+                    return;
+                }
+                warned.push(feature.to_owned());
+            }
+            let mut message = format!("{feature} is deprecated since {since}");
+            if !note.is_empty() {
+                message.push_str(": ");
+                message.push_str(note);
+            }
+            let args = format_args!("{message}");
+            let Some(mut record) = Self::build_record(LogLevel::Warning, Some(callsite), args)
+            else {
+                return;
+            };
+            record.metadata.push(("deprecated.feature".to_owned(), feature.to_owned()));
+            record.metadata.push(("deprecated.since".to_owned(), since.to_owned()));
+            if !note.is_empty() {
+                record.metadata.push(("deprecated.note".to_owned(), note.to_owned()));
+            }
+            Self::dispatch(record);
+        }
+
+        /// Adds `duration` to the histogram for `name`; used by `time_block!`.
+        pub fn record_duration(name: &str, duration: Duration) {
+            HISTOGRAMS
+                .lock()
+                .unwrap()
+                .entry(name.to_owned())
+                .or_insert_with(Histogram::new)
+                .record(duration);
+        }
+
+        /// Percentiles of the durations recorded under `name`.
+        pub fn duration_summary(name: &str) -> Option<DurationSummary> {
+            HISTOGRAMS.lock().unwrap().get(name).map(Histogram::summary)
+        }
+
+        /// Logs one `Info` record per timed name with its count and percentiles.
+        ///
+        /// Call this on a timer (or at shutdown) to get periodic summaries.
+        pub fn log_duration_summaries() {
+            let summaries: Vec<(String, DurationSummary)> = HISTOGRAMS
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(name, histogram)| (name.clone(), histogram.summary()))
+                .collect();
+            for (name, summary) in summaries {
+                Self::info(format_args!(
+                    "duration {name}: count={} p50={:?} p95={:?} p99={:?} max={:?}",
+                    summary.count, summary.p50, summary.p95, summary.p99, summary.max
+                ));
+            }
+        }
+
+        /// Adds `event` to the catalog used by `log_event!`, replacing any event with the same code.
+        pub fn register_event(event: EventCode) {
+            let mut events = EVENT_CODES.write().unwrap();
+            events.retain(|existing| existing.code != event.code);
+            events.push(event);
+        }
+
+        /// Logs a catalogued event; used by `log_event!`.
+        ///
+        /// Unregistered codes are logged at `Info` with their fields appended.
+        pub fn log_event(callsite: Callsite, code: &str, fields: &[(&str, &dyn Display)]) {
+            let event = EVENT_CODES
+                .read()
+                .unwrap()
+                .iter()
+                .find(|event| event.code == code)
+                .cloned();
+            let message = match &event {
+                Some(event) => render_event(event, fields),
+                None => {
+                    let mut message = format!("[{code}]");
+                    for (key, value) in fields {
+                        message.push_str(&format!(" {key}={value}"));
+                    }
+                    message
+                }
+            };
+            let level = event.map_or(LogLevel::Info, |event| event.level);
+            Self::log_msg(level, Some(callsite), format_args!("{message}"));
+        }
+
+        /// Logs `args` at `level` on behalf of `callsite`; used by the logging macros.
+        pub fn log_from(callsite: Callsite, level: LogLevel, args: Arguments) {
+            Self::log_msg(level, Some(callsite), args);
+        }
+
+        /// Runs `init` at the start of the `firo-nonblocking` writer thread used by
+        /// `try_log`, to tune it with the platform's APIs (priority, niceness, pinning to a
+        /// core) so it stays out of the way of latency-sensitive threads. Must be set
+        /// before the first `try_log`, which starts the thread.
+        pub fn set_worker_init<F>(init: F)
+        where
+            F: Fn() + Send + Sync + 'static,
+        {
+            *WORKER_INIT.write().unwrap() = Some(Box::new(init));
+        }
+
+        /// Lets the `try_log` writer collect up to `max_records` queued records and write
+        /// them together, waiting at most `max_latency` after the first for the batch to
+        /// fill. Larger batches cost less per record; the latency bounds how stale a
+        /// record can get. Defaults to writing every record on its own.
+        pub fn set_nonblocking_batching(max_records: usize, max_latency: Duration) {
+            *NONBLOCKING_BATCHING.write().unwrap() = (max_records.max(1), max_latency);
+        }
+
+        /// Logs without ever waiting on the outputs, for latency-critical loops. The record
+        /// is built here and queued for a `firo-nonblocking` writer thread; when the queue
+        /// (or the memory budget) is full it is dropped and `WouldBlock` returned, for the
+        /// caller to count.
+        ///
+        /// Queued records may be written after records logged directly later on, and
+        /// per-thread capture and breadcrumbs don't see them. Used by `try_log!`.
+        pub fn try_log(
+            callsite: Option<Callsite>,
+            level: LogLevel,
+            args: Arguments,
+        ) -> Result<(), WouldBlock> {
+            let Some(record) = Self::build_record(level, callsite, args) else {
+                return Ok(());
+            };
+            let queue = NONBLOCKING_QUEUE.get_or_init(|| {
+                let (sender, receiver) =
+                    std::sync::mpsc::sync_channel::<LogRecord>(NONBLOCKING_CAPACITY);
+                thread::Builder::new()
+                    .name("firo-nonblocking".to_owned())
+                    .spawn(move || {
+                        if let Some(init) = WORKER_INIT.read().unwrap().as_ref() {
+                            init();
+                        }
+                        while let Ok(first) = receiver.recv() {
+                            let (max_records, max_latency) = *NONBLOCKING_BATCHING.read().unwrap();
+                            let batch = collect_batch(&receiver, first, max_records, max_latency);
+                            release_memory(batch.iter().map(LogRecord::buffered_size).sum());
+                            Self::dispatch_batch(batch);
+                        }
+                    })
+                    .ok()
+                    .map(|_| sender)
+            });
+            let Some(queue) = queue else {
+                return Err(WouldBlock);
+            };
+            let size = record.buffered_size();
+            if !reserve_memory(size) {
+                return Err(WouldBlock);
+            }
+            queue.try_send(record).map_err(|_| {
+                release_memory(size);
+                WouldBlock
+            })
+        }
+
+        /// Renders the message without panicking on failing `Display` impls, stopping early
+        /// once the configured maximum length is reached.
+        fn render_message(message: Arguments) -> String {
+            Self::render_message_limited(message, MAX_MESSAGE_LEN.load(Ordering::Relaxed))
+        }
+
+        /// `render_message` with an explicit byte limit, where zero means unlimited.
+        pub(crate) fn render_message_limited(message: Arguments, limit: usize) -> String {
+            let mut writer = LimitedWriter {
+                buffer: String::new(),
+                limit,
+                truncated: false,
+            };
+            let _ = fmt::write(&mut writer, message);
+            if writer.truncated {
+                writer.buffer.push_str("…[truncated]");
+            }
+            writer.buffer
+        }
+
+        /// Caps rendered messages at `max_len` bytes (cut on a character boundary), so huge
+        /// `Debug` expansions can't blow up memory or output. `None` removes the cap.
+        pub fn set_max_message_len(max_len: Option<usize>) {
+            MAX_MESSAGE_LEN.store(max_len.unwrap_or(0), Ordering::Relaxed);
+        }
+
+        fn log_msg(level: LogLevel, callsite: Option<Callsite>, message: Arguments) {
+            if let Some(record) = Self::build_record(level, callsite, message) {
+                Self::dispatch(record);
+            }
+        }
+
+        /// Renders a record for a log call, or `None` if its level or callsite is disabled.
+        fn build_record(
+            level: LogLevel,
+            callsite: Option<Callsite>,
+            message: Arguments,
+        ) -> Option<LogRecord> {
+            if !Self::record_enabled(level, callsite.as_ref()) {
+                return None;
+            }
+            let suppressed = Self::rate_limit(callsite.as_ref())?;
+            let mut record = LogRecord::new(level, Self::render_message(message));
+            record.callsite = callsite;
+            record.tenant_id = TENANT.with(|tenant| tenant.borrow().clone());
+            Self::note_suppressed(&mut record, suppressed);
+            Some(record)
+        }
+
+        /// Limits every callsite to `max` records per `per`. The first record a callsite
+        /// logs after some were dropped carries a `suppressed=N` field. Records without a
+        /// callsite are never limited.
+        pub fn set_rate_limit(max: u32, per: Duration) {
+            *RATE_LIMIT.write().unwrap() = Some((max, per));
+        }
+
+        /// Removes the rate limit and forgets every callsite's window.
+        pub fn clear_rate_limit() {
+            *RATE_LIMIT.write().unwrap() = None;
+            Self::reset_rate_limits();
+        }
+
+        /// Starts every callsite on a fresh window, e.g. between tests.
+        pub fn reset_rate_limits() {
+            let mut windows = RATE_WINDOWS.lock().unwrap();
+            release_memory(windows.len() * RATE_WINDOW_SIZE);
+            windows.clear();
+        }
+
+        /// `None` when `callsite` is over the rate limit, otherwise how many of its records
+        /// were dropped since the last one that got through.
+        fn rate_limit(callsite: Option<&Callsite>) -> Option<u32> {
+            let (Some((max, per)), Some(callsite)) = (*RATE_LIMIT.read().unwrap(), callsite) else {
+                return Some(0);
+            };
+            let now = Self::now();
+            let mut windows = RATE_WINDOWS.lock().unwrap();
+            let key = (callsite.file, callsite.line);
+            if !windows.contains_key(&key) && !reserve_memory(RATE_WINDOW_SIZE) {
+                // Over the memory budget: the callsite goes unlimited rather than silent.
+                return Some(0);
+            }
+            windows
+                .entry(key)
+                .or_insert_with(|| RateWindow::new(now))
+                .admit(now, max, per)
+        }
+
+        fn note_suppressed(record: &mut LogRecord, suppressed: u32) {
+            if suppressed > 0 {
+                record.metadata.push(("suppressed".to_owned(), suppressed.to_string()));
+            }
+        }
+
+        /// Whether a record at `level` from `callsite` passes the level filters (or is
+        /// kept as a breadcrumb) and its callsite isn't disabled.
+        fn record_enabled(level: LogLevel, callsite: Option<&Callsite>) -> bool {
+            let module = callsite.map(|callsite| callsite.module);
+            let breadcrumb = level == LogLevel::Debug
+                && BREADCRUMBS.with(|crumbs| crumbs.borrow().is_some());
+            let overridden = LEVEL_OVERRIDE.with(|min| {
+                min.borrow().is_some_and(|min| level.severity() >= min.severity())
+            });
+            (breadcrumb || overridden || Self::level_enabled(level, module))
+                && callsite.is_none_or(Self::callsite_enabled)
+        }
+
+        /// Logs a record built by the caller, e.g. an adapter for another logging API. It
+        /// passes the same level and callsite checks as the macros before filters,
+        /// enrichment, counts and outputs. Records without a tenant get the thread's.
+        pub fn log_record(mut record: LogRecord) {
+            if !Self::record_enabled(record.level, record.callsite.as_ref()) {
+                return;
+            }
+            let Some(suppressed) = Self::rate_limit(record.callsite.as_ref()) else {
+                return;
+            };
+            Self::note_suppressed(&mut record, suppressed);
+            if record.tenant_id.is_none() {
+                record.tenant_id = TENANT.with(|tenant| tenant.borrow().clone());
+            }
+            Self::dispatch(record);
+        }
+
+        /// `log_record` for callers that want to know whether the record was written:
+        /// returns the first error hit on the way instead of passing it to the error
+        /// handler (later ones still go there). A record dropped by a level, callsite or
+        /// filter is not an error.
+        pub fn emit(record: LogRecord) -> Result<(), LoggerError> {
+            let previous = EMIT_ERRORS.with(|errors| errors.replace(Some(Vec::new())));
+            Self::log_record(record);
+            let errors = EMIT_ERRORS.with(|errors| errors.replace(previous));
+            let mut errors = errors.unwrap_or_default().into_iter();
+            let first = errors.next();
+            for error in errors {
+                Self::report_error(error);
+            }
+            first.map_or(Ok(()), Err)
+        }
+
+        /// Logs with extra `key=value` fields; used by `log_with_metadata!`. Values are
+        /// only formatted once the level and callsite checks have passed.
+        pub fn log_with_metadata(
+            callsite: Callsite,
+            level: LogLevel,
+            fields: &[(&str, &dyn FieldValue)],
+            args: Arguments,
+        ) {
+            let Some(mut record) = Self::build_record(level, Some(callsite), args) else {
+                return;
+            };
+            record.metadata = fields
+                .iter()
+                .map(|(key, value)| {
+                    let mut rendered = String::new();
+                    // A failing Display impl leaves what it wrote so far.
+                    let _ = value.write_value(&mut rendered);
+                    ((*key).to_owned(), rendered)
+                })
+                .collect();
+            record.field_units = fields
+                .iter()
+                .filter_map(|(key, value)| Some(((*key).to_owned(), value.unit()?)))
+                .collect();
+            Self::dispatch(record);
+        }
+
+        /// Filters, enriches, counts and writes a fully built record.
+        pub(crate) fn dispatch(record: LogRecord) {
+            let mut record = Some(record);
+            let flushed = BREADCRUMBS.with(|crumbs| {
+                let mut crumbs = crumbs.borrow_mut();
+                let crumbs = crumbs.as_mut()?;
+                match record.as_ref()?.level {
+                    LogLevel::Debug => {
+                        if crumbs.records.len() == crumbs.capacity {
+                            crumbs.records.pop_front();
+                        }
+                        crumbs.records.extend(record.take());
+                        None
+                    }
+                    LogLevel::Critical | LogLevel::Error => {
+                        Some(std::mem::take(&mut crumbs.records))
+                    }
+                    _ => None,
+                }
+            });
+            let Some(record) = record else {
+                return;
+            };
+            if let Some(mut flushed) = flushed.filter(|flushed| !flushed.is_empty()) {
+                flushed.push_back(record);
+                Self::dispatch_batch(flushed.into());
+                return;
+            }
+            if let Some(record) = Self::prepare(record) {
+                let _emit = EMIT_LOCK.read().unwrap();
+                Self::write_record(&record);
+            }
+        }
+
+        /// Writes the records of a `LogBatch` with no other records in between.
+        fn dispatch_batch(records: Vec<LogRecord>) {
+            let records: Vec<LogRecord> = records.into_iter().filter_map(Self::prepare).collect();
+            {
+                let _emit = EMIT_LOCK.write().unwrap();
+                for record in &records {
+                    Self::write_record(record);
+                }
+            }
+        }
+
+        /// When the earliest `expires` written by this process and not yet removed by
+        /// `expire_records` falls, for scheduling the janitor.
+        pub fn next_expiry() -> Option<SystemTime> {
+            match NEXT_EXPIRY.load(Ordering::Relaxed) {
+                u64::MAX => None,
+                secs => Some(UNIX_EPOCH + Duration::from_secs(secs)),
+            }
+        }
+
+        /// The retention janitor: removes expired records (those whose `expires` field is
+        /// in the past) from this program's log files in the log directory, including
+        /// earlier runs' session files, and from route files, and returns how many were
+        /// removed. Logging never runs it; call it at startup and then periodically, e.g.
+        /// from a thread sleeping until `Logger::next_expiry`.
+        ///
+        /// Text, pattern (`%kv`), syslog and JSON lines are understood; pretty JSON is not.
+        /// Each file is rewritten while this process's writers wait for it alone. Other
+        /// processes appending to a file at the very moment it is replaced can lose those
+        /// lines, so run a single janitor per log directory.
+        pub fn expire_records() -> io::Result<usize> {
+            let dir = Self::log_dir();
+            let stem = Self::script_name();
+            let mut paths: Vec<PathBuf> = std::fs::read_dir(&dir)?
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| {
+                    let name = entry.file_name();
+                    let name = name.to_string_lossy();
+                    owned_log_file(&name, &stem)
+                })
+                .map(|entry| entry.path())
+                .collect();
+            for route in ROUTES.read().unwrap().iter() {
+                if !paths.contains(&route.path) && route.path.exists() {
+                    paths.push(route.path.clone());
+                }
+            }
+
+            let now = Self::now();
+            let previous = NEXT_EXPIRY.swap(u64::MAX, Ordering::Relaxed);
+            let mut removed = 0;
+            let mut first_error = None;
+            for path in paths {
+                let summary = {
+                    let _emit = EMIT_LOCK.write().unwrap();
+                    crate::privacy::expire_records(&path, now)
+                };
+                match summary {
+                    Ok(summary) => {
+                        removed += summary.records_removed;
+                        if let Some(next) = summary.next_expiry {
+                            let next =
+                                next.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                            NEXT_EXPIRY.fetch_min(next, Ordering::Relaxed);
+                        }
+                    }
+                    Err(err) => {
+                        first_error.get_or_insert(err);
+                    }
+                }
+            }
+            match first_error {
+                Some(err) => {
+                    // Files that failed keep their expired records, so keep them due.
+                    NEXT_EXPIRY.fetch_min(previous, Ordering::Relaxed);
+                    Err(err)
+                }
+                None => Ok(removed),
+            }
+        }
+
+        /// Resolves the record's retention hint, from its `retention` field or the routes
+        /// it matches, into an `expires` field.
+        fn stamp_expiry(record: &mut LogRecord) {
+            let route_ttl = ROUTES
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|route| route.retention.is_some() && route.matches(record))
+                .filter_map(|route| route.retention)
+                .min();
+            let record_ttl = match record.field("retention") {
+                Some(hint) => {
+                    let ttl = parse_retention(hint);
+                    if ttl.is_none() {
+                        Self::report_error(LoggerError::Config {
+                            setting: "retention".to_string(),
+                            value: hint.to_owned(),
+                            expected: "a number followed by s, m, h or d, e.g. 30d, of at most \
+                                       36500d"
+                                .to_string(),
+                        });
+                    }
+                    ttl
+                }
+                None => {
+                    if let Some(ttl) = route_ttl {
+                        record.metadata.push(("retention".to_owned(), format_retention(ttl)));
+                    }
+                    None
+                }
+            };
+            let ttl = match (record_ttl, route_ttl) {
+                (Some(record_ttl), Some(route_ttl)) => Some(record_ttl.min(route_ttl)),
+                (ttl, None) | (None, ttl) => ttl,
+            };
+            // A route retention can be any `Duration`; one reaching past the end of time
+            // means the record never expires.
+            if let Some(expires) = ttl.and_then(|ttl| record.timestamp.checked_add(ttl)) {
+                let expires = expires.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                record.metadata.push(("expires".to_owned(), expires.to_string()));
+                NEXT_EXPIRY.fetch_min(expires, Ordering::Relaxed);
+            }
+        }
+
+        /// Runs the filters, then enriches and counts the records that pass. Enrichment
+        /// and sequence numbers are only paid for by records that pass the filters.
+        fn prepare(mut record: LogRecord) -> Option<LogRecord> {
+            if !Self::passes_filters(&record) {
+                return None;
+            }
+            if let Some(callsite) = record.callsite {
+                if !MODULE_FIELDS.read().unwrap().is_empty() {
+                    if let Some(fields) = Self::module_fields(callsite.module) {
+                        record.shared_metadata.push(fields);
+                    }
+                }
+            }
+            for enricher in ENRICHERS.read().unwrap().iter() {
+                record.shared_metadata.push(enricher.fields());
+            }
+            Self::stamp_expiry(&mut record);
+            if INCLUDE_SEQUENCE.load(Ordering::Relaxed) {
+                record.sequence = Some(NEXT_SEQUENCE.fetch_add(1, Ordering::Relaxed));
+            }
+            #[cfg(feature = "event-id")]
+            if INCLUDE_EVENT_ID.load(Ordering::Relaxed) {
+                record.event_id = Some(new_event_id(record.timestamp));
+            }
+            match record.level {
+                LogLevel::Critical | LogLevel::Error => {
+                    ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+                    let millis = record.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+                    LAST_ERROR_AT.store(millis.as_millis() as u64, Ordering::Relaxed);
+                }
+                LogLevel::Warning => {
+                    WARNING_COUNT.fetch_add(1, Ordering::Relaxed);
+                }
+                _ => {}
+            }
+            let millis = record.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+            let millis = millis.as_millis() as u64;
+            if clock_stepped_back(LATEST_TIMESTAMP.fetch_max(millis, Ordering::Relaxed), millis) {
+                CLOCK_CORRECTIONS.fetch_add(1, Ordering::Relaxed);
+            }
+            {
+                let mut counts = LEVEL_COUNTS.lock().unwrap();
+                match counts.iter_mut().find(|(level, _)| *level == record.level) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((record.level, 1)),
+                }
+            }
+            Some(record)
+        }
+
+        /// Formats `record` for each output that will actually receive it, at most once per layout.
+        fn write_record(record: &LogRecord) {
+            #[cfg(feature = "log")]
+            Self::forward_to_log(record);
+
+            let file_line_cell = OnceCell::new();
+            let file_format = *FILE_FORMAT.read().unwrap();
+            let file_fields = *FILE_FIELDS.read().unwrap();
+            let file_line = || {
+                file_line_cell
+                    .get_or_init(|| Self::format_as(record, file_format, false, file_fields))
+            };
+
+            let captured_on_thread = THREAD_CAPTURE.with(|capture| match capture.borrow_mut().as_mut() {
+                Some(captured) => {
+                    captured.push(file_line().clone());
+                    true
+                }
+                None => false,
+            });
+            if captured_on_thread {
+                return;
+            }
+            if let Some(captured) = CAPTURE.lock().unwrap().as_mut() {
+                captured.push(file_line().clone());
+                return;
+            }
+
+            if let Some(remote) = REMOTE_WRITER.read().unwrap().as_ref() {
+                if let Err(err) = remote.send(record) {
+                    Self::report_error(LoggerError::RemoteWrite(err));
+                }
+            }
+            if let Some(tcp) = TCP_WRITER.read().unwrap().as_ref() {
+                if let Err(err) = tcp.send(record) {
+                    Self::report_error(LoggerError::RemoteWrite(err));
+                }
+            }
+            if let Some(loki) = LOKI_WRITER.read().unwrap().as_ref() {
+                if let Err(err) = loki.send(record) {
+                    Self::report_error(LoggerError::RemoteWrite(err));
+                }
+            }
+            #[cfg(feature = "syslog")]
+            if let Some(syslog) = SYSLOG_WRITER.read().unwrap().as_ref() {
+                if let Err(err) = syslog.send(record) {
+                    Self::report_error(LoggerError::RemoteWrite(err));
+                }
+            }
+
+            let output = *OUTPUT.read().unwrap();
+            let stream = Self::stream_for(record.level);
+            let hook = CONSOLE_HOOK.read().unwrap();
+            let console_open = hook.is_some() || !stream.closed_flag().load(Ordering::Relaxed);
+            let console_level = CONSOLE_LEVEL.read().unwrap().allows(record.level);
+            if matches!(output, Output::ConsoleAndFile | Output::Console)
+                && console_open
+                && console_level
+            {
+                let colours = COLOURS_ENABLED.load(Ordering::Relaxed);
+                let console_format = *CONSOLE_FORMAT.read().unwrap();
+                let console_fields = *CONSOLE_FIELDS.read().unwrap();
+                let console_line =
+                    if console_format == file_format && console_fields == file_fields && !colours {
+                        Cow::Borrowed(file_line().as_str())
+                    } else {
+                        Cow::Owned(Self::format_as(record, console_format, colours, console_fields))
+                    };
+                match hook.as_ref() {
+                    Some(hook) => hook(&console_line),
+                    None => Self::console_write(&console_line, stream),
+                }
+            }
+            match output {
+                Output::ConsoleAndFile | Output::File => {
+                    if FILE_LEVEL.read().unwrap().allows(record.level) {
+                        if let Err(error) = Self::file_log(record, file_line()) {
+                            Self::report_error(error);
+                        }
+                    }
+                    for route in ROUTES.read().unwrap().iter() {
+                        if route.matches(record) {
+                            if let Err(error) = Self::append_line(&route.path, file_line()) {
+                                Self::report_error(error);
+                            }
+                        }
+                    }
+                }
+                Output::None => {
+                    file_line();
+                }
+                Output::Console => {}
+            }
+            if record.level == LogLevel::Critical && FLUSH_ON_CRITICAL.load(Ordering::Relaxed) {
+                Self::flush();
+            }
+        }
+
+        /// Flushes the console streams and the remote, TCP, Loki and syslog writers. Log files need
+        /// no flushing: each line is handed to the OS as soon as it is written.
+        pub fn flush() {
+            let _ = io::stdout().flush();
+            let _ = io::stderr().flush();
+            if let Some(remote) = REMOTE_WRITER.read().unwrap().as_ref() {
+                if let Err(err) = remote.flush() {
+                    Self::report_error(LoggerError::RemoteWrite(err));
+                }
+            }
+            if let Some(tcp) = TCP_WRITER.read().unwrap().as_ref() {
+                if let Err(err) = tcp.flush() {
+                    Self::report_error(LoggerError::RemoteWrite(err));
+                }
+            }
+            if let Some(loki) = LOKI_WRITER.read().unwrap().as_ref() {
+                if let Err(err) = loki.flush() {
+                    Self::report_error(LoggerError::RemoteWrite(err));
+                }
+            }
+            #[cfg(feature = "syslog")]
+            if let Some(syslog) = SYSLOG_WRITER.read().unwrap().as_ref() {
+                if let Err(err) = syslog.flush() {
+                    Self::report_error(LoggerError::RemoteWrite(err));
+                }
+            }
+        }
+
+        /// Calls `Logger::flush` after every Critical record, so it isn't left in a buffer
+        /// if the process dies right after logging it. Off by default.
+        pub fn set_flush_on_critical(enabled: bool) {
+            FLUSH_ON_CRITICAL.store(enabled, Ordering::Relaxed);
+        }
+
+        /// Forwards every record that passes the filters to `backend` (e.g. an `env_logger`
+        /// or `log4rs` logger), in addition to firo's own outputs.
+        ///
+        /// Combine with `Logger::set_output(Output::None)` to ship only through `backend`.
+        #[cfg(feature = "log")]
+        pub fn set_log_backend(backend: Box<dyn log::Log>) {
+            *LOG_BACKEND.write().unwrap() = Some(backend);
+        }
+
+        /// Stops forwarding records to the `log` backend.
+        #[cfg(feature = "log")]
+        pub fn clear_log_backend() {
+            *LOG_BACKEND.write().unwrap() = None;
+        }
+
+        #[cfg(feature = "log")]
+        fn forward_to_log(record: &LogRecord) {
+            let backend = LOG_BACKEND.read().unwrap();
+            let Some(backend) = backend.as_ref() else {
+                return;
+            };
+            let level = match record.level {
+                LogLevel::Critical | LogLevel::Error => log::Level::Error,
+                LogLevel::Warning => log::Level::Warn,
+                LogLevel::Debug => log::Level::Debug,
+                LogLevel::Success | LogLevel::Info | LogLevel::Log => log::Level::Info,
+                LogLevel::Custom(_) => match record.level.severity() {
+                    0 => log::Level::Debug,
+                    1 | 2 => log::Level::Info,
+                    3 => log::Level::Warn,
+                    _ => log::Level::Error,
+                },
+            };
+            let module = record.callsite.map(|callsite| callsite.module);
+            let target = module.unwrap_or("firo_logger");
+            let metadata = log::Metadata::builder().level(level).target(target).build();
+            if !backend.enabled(&metadata) {
+                return;
+            }
+            backend.log(
+                &log::Record::builder()
+                    .metadata(metadata)
+                    .args(format_args!(
+                        "{}{}",
+                        record.message,
+                        Self::interop_fields(record, Self::success_mapping())
+                    ))
+                    .module_path(module)
+                    .file(record.callsite.map(|callsite| callsite.display_file()))
+                    .line(record.callsite.map(|callsite| callsite.line))
+                    .build(),
+            );
+        }
+
+        /// Sets how Success records are translated for systems without a success level.
+        pub fn set_success_mapping(mapping: SuccessMapping) {
+            *SUCCESS_MAPPING.write().unwrap() = Some(mapping);
+        }
+
+        pub(crate) fn success_mapping() -> SuccessMapping {
+            SUCCESS_MAPPING.read().unwrap().unwrap_or_default()
+        }
+
+        /// `format_fields` plus the `outcome=success` field where `mapping` asks for it,
+        /// for outputs whose level can't say "success" on its own.
+        #[cfg(any(feature = "log", test))]
+        pub(crate) fn interop_fields(record: &LogRecord, mapping: SuccessMapping) -> String {
+            let mut fields = Self::format_fields(record);
+            if record.level == LogLevel::Success && mapping.outcome_field {
+                fields.push_str(" outcome=success");
+            }
+            fields
+        }
+
+        /// Also sends every record to a `firo_logger::remote` server.
+        pub fn set_remote_writer(writer: RemoteWriter) {
+            *REMOTE_WRITER.write().unwrap() = Some(writer);
+        }
+
+        /// Stops sending records to the remote server.
+        pub fn clear_remote_writer() {
+            *REMOTE_WRITER.write().unwrap() = None;
+        }
+
+        /// Also streams every record to a TCP input such as logstash's or vector's.
+        /// Failed connection attempts go to the error handler as
+        /// `LoggerError::RemoteWrite`.
+        pub fn set_tcp_writer(writer: TcpWriter) {
+            *TCP_WRITER.write().unwrap() = Some(writer);
+        }
+
+        /// Stops streaming records over TCP; queued lines are dropped.
+        pub fn clear_tcp_writer() {
+            *TCP_WRITER.write().unwrap() = None;
+        }
+
+        /// Also pushes every record to Grafana Loki. Failed pushes go to the error
+        /// handler as `LoggerError::RemoteWrite`; call `Logger::flush` before exiting so
+        /// the last batch is sent.
+        pub fn set_loki_writer(writer: LokiWriter) {
+            *LOKI_WRITER.write().unwrap() = Some(writer);
+        }
+
+        /// Stops pushing to Loki, after pushing the waiting records.
+        pub fn clear_loki_writer() {
+            let Some(writer) = LOKI_WRITER.write().unwrap().take() else {
+                return;
+            };
+            if let Err(err) = writer.flush() {
+                Self::report_error(LoggerError::RemoteWrite(err));
+            }
+        }
+
+        /// Also sends every record to a syslog daemon. Send failures go to the error
+        /// handler as `LoggerError::RemoteWrite`.
+        #[cfg(feature = "syslog")]
+        pub fn set_syslog_writer(writer: SyslogWriter) {
+            *SYSLOG_WRITER.write().unwrap() = Some(writer);
+        }
+
+        /// Stops sending records to syslog.
+        #[cfg(feature = "syslog")]
+        pub fn clear_syslog_writer() {
+            *SYSLOG_WRITER.write().unwrap() = None;
+        }
+
+        /// Hands console lines to `hook` instead of printing them, so output can
+        /// cooperate with progress bars, e.g. `Logger::set_console_hook(move |line| bar.println(line))`.
+        pub fn set_console_hook<F>(hook: F)
+        where
+            F: Fn(&str) + Send + Sync + 'static,
+        {
+            *CONSOLE_HOOK.write().unwrap() = Some(Box::new(hook));
+            Self::check_conflicts();
+        }
+
+        /// Goes back to printing console lines with `println!`.
+        pub fn clear_console_hook() {
+            *CONSOLE_HOOK.write().unwrap() = None;
+        }
+
+        /// Shows the caller's module in text lines, shortened according to `style`.
+        pub fn set_module_style(style: ModuleStyle) {
+            *MODULE_STYLE.write().unwrap() = style;
+        }
+
+        /// Drops the leading crate name from displayed module paths.
+        pub fn set_strip_crate_name(enabled: bool) {
+            STRIP_CRATE_NAME.store(enabled, Ordering::Relaxed);
+        }
+
+        /// Strips `prefix` (e.g. `env!("CARGO_MANIFEST_DIR")`) from caller file paths, so
+        /// output shows `src/handlers/user.rs:42` rather than an absolute build path.
+        /// `None` shows paths as compiled.
+        pub fn set_source_prefix(prefix: Option<&str>) {
+            *SOURCE_PREFIX.write().unwrap() = prefix.map(str::to_owned);
+        }
+
+        /// Applies the `FIRO_LOG_*` environment variables. Invalid values are reported to
+        /// the error handler and skipped; the valid ones still take effect. With file
+        /// output, an unwritable log file is reported too (see `check_file_output`).
+        pub fn configure_from_env() {
+            let (config, errors) = EnvConfig::read(|name| env::var(name).ok());
+            for error in errors {
+                Self::report_error(error);
+            }
+            config.apply();
+            if let Err(error) = Self::verify_file_output() {
+                Self::report_error(error);
+            }
+        }
+
+        /// Like `configure_from_env`, but applies nothing and returns the first invalid
+        /// value as a `LoggerError::Config`, so typos fail start-up instead of going unnoticed.
+        /// An unwritable log file is returned as `LoggerError::FileUnwritable`.
+        pub fn configure_from_env_strict() -> Result<(), LoggerError> {
+            let (config, errors) = EnvConfig::read(|name| env::var(name).ok());
+            if let Some(error) = errors.into_iter().next() {
+                return Err(error);
+            }
+            config.apply();
+            Self::verify_file_output()
+        }
+
+        /// Runs `check_file_output` if the output includes the log file. Under the
+        /// `set_file_fallback` policy a failure switches to console-only output and logs
+        /// a Warning saying why, instead of being returned.
+        pub fn verify_file_output() -> Result<(), LoggerError> {
+            if !matches!(*OUTPUT.read().unwrap(), Output::ConsoleAndFile | Output::File) {
+                return Ok(());
+            }
+            match Self::check_file_output() {
+                Ok(_) => Ok(()),
+                Err(error) => Self::fall_back_to_console(error),
+            }
+        }
+
+        /// Applies the `set_file_fallback` policy to an unwritable log file.
+        pub(crate) fn fall_back_to_console(error: LoggerError) -> Result<(), LoggerError> {
+            if !FILE_FALLBACK.load(Ordering::Relaxed) {
+                return Err(error);
+            }
+            Self::set_output(Output::Console);
+            let mut record = LogRecord::new(
+                LogLevel::Warning,
+                format!("Logging to the console only, the log file can't be used: {error}"),
+            );
+            record.metadata.push(("error_code".to_owned(), error.code().to_owned()));
+            Self::log_record(record);
+            Ok(())
+        }
+
+        /// When the log file turns out unwritable at start-up (read-only file system,
+        /// missing mount), fall back to console-only output with a Warning rather than
+        /// failing `configure_from_env_strict` or `verify_file_output`. Off by default.
+        pub fn set_file_fallback(enabled: bool) {
+            FILE_FALLBACK.store(enabled, Ordering::Relaxed);
+        }
+
+        /// Checks at start-up that the main log file can be created and appended to, so
+        /// a bad path fails with a clear `LoggerError::FileUnwritable` (absolute path,
+        /// directory and its permissions) instead of on the first record. Returns
+        /// the absolute path. A file created by the check is removed again.
+        pub fn check_file_output() -> Result<PathBuf, LoggerError> {
+            Self::probe_file(Self::log_file_path(false, None))
+        }
+
+        pub(crate) fn probe_file(path: PathBuf) -> Result<PathBuf, LoggerError> {
+            let path = std::path::absolute(&path).unwrap_or(path);
+            let existed = path.exists();
+            match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(_) => {
+                    if !existed {
+                        let _ = std::fs::remove_file(&path);
+                    }
+                    Ok(path)
+                }
+                Err(source) => Err(LoggerError::FileUnwritable {
+                    diagnostics: file_diagnostics(&path),
+                    path,
+                    source,
+                }),
+            }
+        }
+
+        /// Snapshot of the effective settings.
+        pub fn settings() -> LoggerSettings {
+            let flag = |flag: &AtomicBool| flag.load(Ordering::Relaxed).to_string();
+            let mut entries = vec![
+                ("level", format!("{:?}", *LEVEL.read().unwrap())),
+                ("module_levels", format!("{:?}", *MODULE_LEVELS.read().unwrap())),
+                ("module_fields", format!("{:?}", *MODULE_FIELDS.read().unwrap())),
+                ("console_level", format!("{:?}", *CONSOLE_LEVEL.read().unwrap())),
+                ("file_level", format!("{:?}", *FILE_LEVEL.read().unwrap())),
+                ("quiet_period", format!("{:?}", *QUIET_PERIOD.read().unwrap())),
+                ("output", format!("{:?}", *OUTPUT.read().unwrap())),
+                ("file_fallback", flag(&FILE_FALLBACK)),
+                ("console_format", format!("{:?}", *CONSOLE_FORMAT.read().unwrap())),
+                ("file_format", format!("{:?}", *FILE_FORMAT.read().unwrap())),
+                ("json_severity_scale", format!("{:?}", *JSON_SEVERITY_SCALE.read().unwrap())),
+                ("console_fields", format!("{:?}", *CONSOLE_FIELDS.read().unwrap())),
+                ("file_fields", format!("{:?}", *FILE_FIELDS.read().unwrap())),
+                ("log_file", Self::log_file_path(false, None).display().to_string()),
+                ("disk_budget", DISK_BUDGET.load(Ordering::Relaxed).to_string()),
+                ("memory_budget", MEMORY_BUDGET.load(Ordering::Relaxed).to_string()),
+                ("colours", flag(&COLOURS_ENABLED)),
+                ("utc_timestamps", flag(&UTC_TIMESTAMPS)),
+                ("timestamp_format", format!("{:?}", *TIMESTAMP_FORMAT.read().unwrap())),
+                ("per_thread_files", flag(&PER_THREAD_FILES)),
+                ("per_tenant_files", flag(&PER_TENANT_FILES)),
+                ("include_sequence", flag(&INCLUDE_SEQUENCE)),
+                ("flush_on_critical", flag(&FLUSH_ON_CRITICAL)),
+            ];
+            #[cfg(feature = "event-id")]
+            entries.push(("include_event_id", flag(&INCLUDE_EVENT_ID)));
+            #[cfg(feature = "chrono")]
+            entries.push((
+                "datetime_format",
+                DATETIME_FORMAT
+                    .read()
+                    .unwrap()
+                    .clone()
+                    .unwrap_or_else(|| DATETIME_PATTERN.to_owned()),
+            ));
+            let max_message_len = match MAX_MESSAGE_LEN.load(Ordering::Relaxed) {
+                0 => "unlimited".to_owned(),
+                len => len.to_string(),
+            };
+            entries.extend([
+                ("max_message_len", max_message_len),
+                ("success_mapping", format!("{:?}", Self::success_mapping())),
+                ("module_style", format!("{:?}", *MODULE_STYLE.read().unwrap())),
+                ("strip_crate_name", flag(&STRIP_CRATE_NAME)),
+                ("markup", flag(&MARKUP_ENABLED)),
+                (
+                    "source_prefix",
+                    SOURCE_PREFIX.read().unwrap().clone().unwrap_or_else(|| "none".to_owned()),
+                ),
+                ("default_stream", format!("{:?}", *DEFAULT_STREAM.read().unwrap())),
+                ("level_streams", format!("{:?}", *LEVEL_STREAMS.read().unwrap())),
+                ("console_hook", CONSOLE_HOOK.read().unwrap().is_some().to_string()),
+                ("remote_writer", REMOTE_WRITER.read().unwrap().is_some().to_string()),
+                ("tcp_writer", TCP_WRITER.read().unwrap().is_some().to_string()),
+                ("loki_writer", LOKI_WRITER.read().unwrap().is_some().to_string()),
+                ("filters", FILTERS.read().unwrap().len().to_string()),
+                ("routes", ROUTES.read().unwrap().len().to_string()),
+                ("enrichers", ENRICHERS.read().unwrap().len().to_string()),
+                ("disabled_callsites", DISABLED_CALLSITES.read().unwrap().len().to_string()),
+                ("rate_limit", format!("{:?}", *RATE_LIMIT.read().unwrap())),
+            ]);
+            #[cfg(feature = "syslog")]
+            entries.push((
+                "syslog_writer",
+                SYSLOG_WRITER.read().unwrap().is_some().to_string(),
+            ));
+            LoggerSettings { entries }
+        }
+
+        /// Human-readable summary of the effective settings, handy to log at Debug
+        /// during start-up.
+        pub fn describe() -> String {
+            Self::settings().to_string()
+        }
+
+        /// Writes log files into `dir`, creating it if needed, instead of the working directory.
+        pub fn set_log_dir<P: Into<PathBuf>>(dir: P) -> io::Result<()> {
+            let dir = dir.into();
+            std::fs::create_dir_all(&dir)?;
+            *LOG_DIR.write().unwrap() = Some(dir);
+            Ok(())
+        }
+
+        /// Writes log files into the platform's log directory for `app_name` (see
+        /// `default_log_dir`) and returns it.
+        pub fn use_default_log_dir(app_name: &str) -> io::Result<PathBuf> {
+            let dir = default_log_dir(app_name).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "no home or state directory found")
+            })?;
+            Self::set_log_dir(dir.clone())?;
+            Ok(dir)
+        }
+
+        /// Caps the total size of this program's log files in the log directory (current
+        /// and session files, per-thread and per-tenant files; other files are never
+        /// touched). When it is exceeded, the least recently modified files are deleted;
+        /// the file being written is kept. Checked at most
+        /// once a second while writing. `None` removes the budget.
+        pub fn set_disk_budget(max_bytes: Option<u64>) {
+            DISK_BUDGET.store(max_bytes.unwrap_or(0), Ordering::Relaxed);
+        }
+
+        /// Gives this run its own log file, `<app>-YYYYMMDD-HHMMSS-PID.log` (start time in
+        /// UTC), instead of appending to `<app>.log`. With `keep`, files of older sessions
+        /// in the log directory are deleted so only the newest `keep` sessions,
+        /// including this one, remain. Returns how many files were deleted.
+        pub fn start_session(keep: Option<usize>) -> io::Result<usize> {
+            let suffix = session_suffix(Self::now(), std::process::id());
+            *SESSION.write().unwrap() = Some(suffix);
+            match keep {
+                Some(keep) => prune_sessions(&Self::log_dir(), &Self::script_name(), keep.max(1)),
+                None => Ok(0),
+            }
+        }
+
+        /// Selects the log file layout (also used for captured lines). Defaults to `Text`.
+        pub fn set_file_format(format: OutputFormat) {
+            *FILE_FORMAT.write().unwrap() = format;
+        }
+
+        /// Sets the columns of CSV and TSV lines.
+        pub fn set_csv_columns(columns: Vec<CsvColumn>) {
+            *CSV_COLUMNS.write().unwrap() = columns;
+        }
+
+        /// Interprets `<b>`, `<i>`, `<u>`, `<dim>` and colour tags (`<red>`, `<green>`,
+        /// `<yellow>`, `<blue>`, `<cyan>`) in messages, each closed by its `</tag>`. They
+        /// become ANSI codes on a coloured console and are stripped from files and plain
+        /// output. Tags don't nest: a closing tag resets all styling. Off by default, so
+        /// messages containing `<` are left alone.
+        pub fn set_markup(enabled: bool) {
+            MARKUP_ENABLED.store(enabled, Ordering::Relaxed);
+        }
+
+        /// Selects the console layout.
+        pub fn set_console_format(format: OutputFormat) {
+            *CONSOLE_FORMAT.write().unwrap() = format;
+        }
+
+        /// Sets where the console's `Text` and `Pretty` lines put record fields and
+        /// whether they are sorted by key, e.g. a sorted block below each message.
+        pub fn set_console_fields(style: FieldStyle) {
+            *CONSOLE_FIELDS.write().unwrap() = style;
+        }
+
+        /// Like `Logger::set_console_fields`, for log file (and captured) lines.
+        pub fn set_file_fields(style: FieldStyle) {
+            *FILE_FIELDS.write().unwrap() = style;
+        }
+
+        /// Only prints records `filter` allows on the console, e.g. Warning and up while
+        /// the log file keeps everything. Applies on top of `Logger::set_level`, which
+        /// must let through whatever any output should get.
+        pub fn set_console_level(filter: LevelFilter) {
+            *CONSOLE_LEVEL.write().unwrap() = filter;
+        }
+
+        /// Only writes records `filter` allows to the main log file; route files have
+        /// their own level settings. Applies on top of `Logger::set_level`.
+        pub fn set_file_level(filter: LevelFilter) {
+            *FILE_LEVEL.write().unwrap() = filter;
+        }
+
+        /// Selects where records go; `Output::None` silences the logger.
+        pub fn set_output(output: Output) {
+            *OUTPUT.write().unwrap() = output;
+            Self::check_conflicts();
+        }
+
+        /// Turns ANSI colours on the console on or off.
+        ///
+        /// Has no effect when the crate is built without the `colours` feature.
+        pub fn set_colours(enabled: bool) {
+            if enabled && !cfg!(feature = "colours") {
+                Self::report_conflict("colours were enabled but the `colours` feature is off");
+            }
+            COLOURS_ENABLED.store(enabled && cfg!(feature = "colours"), Ordering::Relaxed);
+        }
+
+        /// Writes each thread's records to its own file, `<app>.<thread name>.log`.
+        ///
+        /// Unnamed threads use `thread-<id>` in place of a name.
+        pub fn set_per_thread_files(enabled: bool) {
+            PER_THREAD_FILES.store(enabled, Ordering::Relaxed);
+            Self::check_conflicts();
+        }
+
+        /// Stamps every record with a UUIDv7 `event_id` field.
+        #[cfg(feature = "event-id")]
+        pub fn set_include_event_id(enabled: bool) {
+            INCLUDE_EVENT_ID.store(enabled, Ordering::Relaxed);
+        }
+
+        /// Numbers every logged record with a monotonically increasing `seq` field,
+        /// so consumers can detect reordering or loss downstream.
+        pub fn set_include_sequence(enabled: bool) {
+            INCLUDE_SEQUENCE.store(enabled, Ordering::Relaxed);
+        }
+
+        /// Sets the chrono `strftime` pattern used by `TimestampFormat::DateTime`, e.g.
+        /// `"%d/%m/%Y %H:%M"`. The pattern is checked with a trial format first, and an
+        /// invalid one is rejected as `LoggerError::Config` instead of producing garbage.
+        #[cfg(feature = "chrono")]
+        pub fn set_datetime_format(pattern: &str) -> Result<(), LoggerError> {
+            validate_datetime_pattern(pattern)?;
+            *DATETIME_FORMAT.write().unwrap() = Some(pattern.to_owned());
+            Ok(())
+        }
+
+        /// Goes back to the default `YYYY-MM-DD HH:MM:SS` pattern.
+        #[cfg(feature = "chrono")]
+        pub fn reset_datetime_format() {
+            *DATETIME_FORMAT.write().unwrap() = None;
+        }
+
+        /// Selects how timestamps are rendered, e.g. as Unix epoch milliseconds for ingestion pipelines.
+        pub fn set_timestamp_format(format: TimestampFormat) {
+            *TIMESTAMP_FORMAT.write().unwrap() = format;
+        }
+
+        /// Tags records logged on this thread with `tenant_id` until the guard is dropped.
+        pub fn set_tenant(tenant_id: &str) -> TenantGuard {
+            let previous = TENANT.with(|tenant| tenant.borrow_mut().replace(tenant_id.to_owned()));
+            TenantGuard { previous }
+        }
+
+        /// Spawns a thread named `name` that starts with this thread's `LogContext`
+        /// (currently the tenant set with `set_tenant`), so its records aren't logged
+        /// without context.
+        pub fn spawn_logged<F, T>(name: &str, f: F) -> io::Result<thread::JoinHandle<T>>
+        where
+            F: FnOnce() -> T + Send + 'static,
+            T: Send + 'static,
+        {
+            let context = LogContext::current();
+            thread::Builder::new()
+                .name(name.to_owned())
+                .spawn(move || context.scope(f))
+        }
+
+        /// Lets records at `level` and above through on this thread until the guard is
+        /// dropped, whatever the global, module and quiet-period levels say, e.g. Debug
+        /// around one suspicious code path in production. Per-output levels and callsite
+        /// switches still apply; other threads are unaffected.
+        pub fn override_level(level: LogLevel) -> LevelGuard {
+            let previous = LEVEL_OVERRIDE.with(|current| current.borrow_mut().replace(level));
+            LevelGuard { previous }
+        }
+
+        /// Runs `f` with `Logger::override_level(level)` in effect.
+        pub fn with_level<R>(level: LogLevel, f: impl FnOnce() -> R) -> R {
+            let _guard = Self::override_level(level);
+            f()
+        }
+
+        /// Holds back Debug records logged on this thread (the last `capacity` of them,
+        /// even when the level filter would drop Debug) until the guard is dropped. They
+        /// are written just before the first Error logged in the scope, and discarded if
+        /// none is, giving failure context without constant Debug volume.
+        pub fn breadcrumbs(capacity: usize) -> BreadcrumbGuard {
+            let crumbs = Breadcrumbs {
+                capacity: capacity.max(1),
+                records: VecDeque::new(),
+            };
+            let previous = BREADCRUMBS.with(|current| current.borrow_mut().replace(crumbs));
+            BreadcrumbGuard { previous }
+        }
+
+        /// Writes records with a tenant to `<app>.<tenant>.log` instead of the shared file.
+        pub fn set_per_tenant_files(enabled: bool) {
+            PER_TENANT_FILES.store(enabled, Ordering::Relaxed);
+            Self::check_conflicts();
+        }
+
+        /// Formats timestamps in UTC instead of local time.
+        ///
+        /// Builds without the `chrono` feature always use UTC.
+        pub fn set_utc_timestamps(enabled: bool) {
+            UTC_TIMESTAMPS.store(enabled, Ordering::Relaxed);
+        }
+
+        /// Redirects all output into memory instead of the console and log file.
+        pub fn start_capture() {
+            CAPTURE.lock().unwrap().get_or_insert_with(Vec::new);
+        }
+
+        /// Returns the lines captured so far and keeps capturing.
+        pub fn take_captured() -> Vec<String> {
+            CAPTURE
+                .lock()
+                .unwrap()
+                .as_mut()
+                .map(std::mem::take)
+                .unwrap_or_default()
+        }
+
+        /// Stops capturing and goes back to console and file output.
+        pub fn stop_capture() -> Vec<String> {
+            CAPTURE.lock().unwrap().take().unwrap_or_default()
+        }
+
+        /// Runs `f` with everything logged on the current thread captured in memory.
+        ///
+        /// Only the captured records are per thread: `f` still runs with the process-wide
+        /// settings (levels, filters, formats, routes), and changes it makes to them are
+        /// seen by every thread. Tests that change settings must still run one at a time.
+        /// This is not a scoped logger per test, which would need a `#[with_logger]`
+        /// attribute macro and a proc-macro crate.
+        pub fn capture<F, R>(f: F) -> (R, Vec<String>)
+        where
+            F: FnOnce() -> R,
+        {
+            let previous = THREAD_CAPTURE.with(|capture| capture.replace(Some(Vec::new())));
+            let result = f();
+            let captured = THREAD_CAPTURE.with(|capture| capture.replace(previous));
+            (result, captured.unwrap_or_default())
+        }
+
+        /// Writes previously captured lines to the current console and log file as they
+        /// are, keeping their original timestamps. Lets a ring of Debug context gathered
+        /// in memory be persisted once a problem is detected, e.g.
+        /// `Logger::replay(&Logger::stop_capture())`.
+        ///
+        /// Each line goes where a record of its level would: the console stream set for
+        /// the level, the console and file levels, per-tenant files and level-based routes.
+        /// Only the level and tenant can be read back from the line, so routes that need
+        /// a module or other fields don't match. Replayed lines skip
+        /// filters, severity counts, remote writers and capture.
+        pub fn replay(lines: &[String]) {
+            let output = *OUTPUT.read().unwrap();
+            let _emit = EMIT_LOCK.read().unwrap();
+            for line in lines {
+                let record = Self::replayed_record(line);
+                if matches!(output, Output::ConsoleAndFile | Output::Console)
+                    && CONSOLE_LEVEL.read().unwrap().allows(record.level)
+                {
+                    let stream = Self::stream_for(record.level);
+                    match CONSOLE_HOOK.read().unwrap().as_ref() {
+                        Some(hook) => hook(line),
+                        None if !stream.closed_flag().load(Ordering::Relaxed) => {
+                            Self::console_write(line, stream)
+                        }
+                        None => {}
+                    }
+                }
+                if !matches!(output, Output::ConsoleAndFile | Output::File) {
+                    continue;
+                }
+                if FILE_LEVEL.read().unwrap().allows(record.level) {
+                    if let Err(error) = Self::file_log(&record, line) {
+                        Self::report_error(error);
+                    }
+                }
+                for route in ROUTES.read().unwrap().iter() {
+                    if route.matches(&record) {
+                        if let Err(error) = Self::append_line(&route.path, line) {
+                            Self::report_error(error);
+                        }
+                    }
+                }
+            }
+        }
+
+        /// A record with the level and tenant of a captured `line`, found as `[LEVEL]` or
+        /// `"level":"LEVEL"` and `tenant_id=...` or `"tenant_id":"..."`. Lines with no
+        /// recognisable level count as Info.
+        pub(crate) fn replayed_record(line: &str) -> LogRecord {
+            let quoted = |key: &str| {
+                let start = line.find(&format!("\"{key}\":\""))? + key.len() + 4;
+                line[start..].split('"').next()
+            };
+            let bracketed = line
+                .split('[')
+                .skip(1)
+                .filter_map(|rest| rest.split_once(']'))
+                .find_map(|(name, _)| LogLevel::from_name(name));
+            let level = bracketed
+                .or_else(|| quoted("level").and_then(LogLevel::from_name))
+                .unwrap_or(LogLevel::Info);
+            let mut record = LogRecord::new(level, "");
+            let tenant = line
+                .split_whitespace()
+                .find_map(|word| word.strip_prefix("tenant_id="))
+                .or_else(|| quoted("tenant_id"));
+            record.tenant_id = tenant.map(str::to_owned);
+            record
+        }
+
+        /// Makes output deterministic for snapshot tests: a fixed timestamp
+        /// (2000-01-01 00:00:00 UTC), no colours and in-memory capture. JSON lines keep
+        /// their members in a fixed order.
+        ///
+        /// The clock, timestamp and colour settings and the capture are process-wide, so
+        /// they change the output of every thread: tests using test mode must not run in
+        /// parallel with other logging tests (hold a shared lock around them).
+        pub fn enable_test_mode() {
+            let clock = CLOCK.write().unwrap().replace(Box::new(FixedClock(
+                UNIX_EPOCH + Duration::from_secs(946_684_800),
+            )));
+            let utc = UTC_TIMESTAMPS.swap(true, Ordering::Relaxed);
+            let colours = COLOURS_ENABLED.swap(false, Ordering::Relaxed);
+            let mut saved = TEST_MODE_SAVED.lock().unwrap();
+            // Enabling twice must not lose the settings from before the first time.
+            if saved.is_none() {
+                *saved = Some(SavedSettings {
+                    clock,
+                    utc,
+                    colours,
+                });
+            }
+            Self::start_capture();
+        }
+
+        /// Undoes `enable_test_mode`, restoring the clock, timestamp and colour settings
+        /// it replaced, and returns whatever was captured.
+        pub fn disable_test_mode() -> Vec<String> {
+            if let Some(saved) = TEST_MODE_SAVED.lock().unwrap().take() {
+                *CLOCK.write().unwrap() = saved.clock;
+                UTC_TIMESTAMPS.store(saved.utc, Ordering::Relaxed);
+                COLOURS_ENABLED.store(saved.colours, Ordering::Relaxed);
+            }
+            Self::stop_capture()
+        }
+
+        pub fn log(args: Arguments) {
+            Self::log_msg(LogLevel::Log, None, args);
+        }
+
+        pub fn critical(args: Arguments) {
+            Self::log_msg(LogLevel::Critical, None, args);
+        }
+
+        pub fn error(args: Arguments) {
+            Self::log_msg(LogLevel::Error, None, args);
+        }
+
+        pub fn warning(args: Arguments) {
+            Self::log_msg(LogLevel::Warning, None, args);
+        }
+
+        pub fn debug(args: Arguments) {
+            Self::log_msg(LogLevel::Debug, None, args);
+        }
+
+        pub fn info(args: Arguments) {
+            Self::log_msg(LogLevel::Info, None, args);
+        }
+
+        pub fn success(args: Arguments) {
+            Self::log_msg(LogLevel::Success, None, args);
+        }
+    }
+
+    /// Builds a UUIDv7 from the record timestamp, so ids sort in time order.
+    #[cfg(feature = "event-id")]
+    pub(crate) fn new_event_id(timestamp: SystemTime) -> String {
+        let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let ts = uuid::Timestamp::from_unix(
+            uuid::NoContext,
+            since_epoch.as_secs(),
+            since_epoch.subsec_nanos(),
+        );
+        uuid::Uuid::new_v7(ts).to_string()
+    }
+
+    /// Renders `[CODE] message`, filling `{field}` placeholders and appending the docs URL.
+    pub(crate) fn render_event(event: &EventCode, fields: &[(&str, &dyn Display)]) -> String {
+        let mut message = event.template.to_owned();
+        for (key, value) in fields {
+            message = message.replace(&format!("{{{key}}}"), &value.to_string());
+        }
+        match event.docs_url {
+            Some(url) => format!("[{}] {message} (see {url})", event.code),
+            None => format!("[{}] {message}", event.code),
+        }
+    }
+
+    /// The platform's conventional log directory for `app_name`:
+    /// `$XDG_STATE_HOME/<app>` (or `~/.local/state/<app>`) on Linux and other Unix,
+    /// `~/Library/Logs/<app>` on macOS and `%LOCALAPPDATA%\<app>\logs` on Windows.
+    /// `None` if the needed environment variables are missing.
+    pub fn default_log_dir(app_name: &str) -> Option<PathBuf> {
+        log_dir_for(env::consts::OS, app_name, |name| env::var_os(name).map(PathBuf::from))
+    }
+
+    pub(crate) fn log_dir_for(
+        os: &str,
+        app_name: &str,
+        var: impl Fn(&str) -> Option<PathBuf>,
+    ) -> Option<PathBuf> {
+        let non_empty = |name| var(name).filter(|path| !path.as_os_str().is_empty());
+        match os {
+            "windows" => Some(non_empty("LOCALAPPDATA")?.join(app_name).join("logs")),
+            "macos" => Some(non_empty("HOME")?.join("Library/Logs").join(app_name)),
+            _ => match non_empty("XDG_STATE_HOME") {
+                Some(state) => Some(state.join(app_name)),
+                None => Some(non_empty("HOME")?.join(".local/state").join(app_name)),
+            },
+        }
+    }
+
+    /// Whether `name` is a log file this crate writes for the program `stem`, going by
+    /// the exact shape of the names it creates: `<stem>[-<session>][.<tenant>][.<thread>].log`.
+    /// Tenant and thread parts are letters, digits, `-` and `_`; files named after threads
+    /// with other characters are never matched, so a lookalike is never deleted.
+    pub(crate) fn owned_log_file(name: &str, stem: &str) -> bool {
+        let Some(rest) = name.strip_prefix(stem).and_then(|rest| rest.strip_suffix(".log")) else {
+            return false;
+        };
+        let (session, parts) = match rest.split_once('.') {
+            Some((session, parts)) => (session, Some(parts)),
+            None => (rest, None),
+        };
+        let session_ok = session.is_empty()
+            || session.strip_prefix('-').is_some_and(is_session_id);
+        let part_ok = |part: &str| {
+            !part.is_empty()
+                && part.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        };
+        session_ok
+            && parts.is_none_or(|parts| {
+                let parts: Vec<&str> = parts.split('.').collect();
+                parts.len() <= 2 && parts.into_iter().all(part_ok)
+            })
+    }
+
+    /// `YYYYMMDD-HHMMSS-PID`, as made by `session_suffix`.
+    fn is_session_id(id: &str) -> bool {
+        let mut parts = id.split('-');
+        let digits = |part: Option<&str>, len: Option<usize>| {
+            part.is_some_and(|part| {
+                !part.is_empty()
+                    && part.bytes().all(|b| b.is_ascii_digit())
+                    && len.is_none_or(|len| part.len() == len)
+            })
+        };
+        digits(parts.next(), Some(8))
+            && digits(parts.next(), Some(6))
+            && digits(parts.next(), None)
+            && parts.next().is_none()
+    }
+
+    /// Longest retention hint accepted, about a hundred years.
+    const MAX_RETENTION_SECS: u64 = 36_500 * 86_400;
+
+    /// Parses a retention hint such as `45s`, `15m`, `12h` or `30d`, of at most
+    /// `36500d`.
+    pub(crate) fn parse_retention(hint: &str) -> Option<Duration> {
+        let unit = match hint.chars().last()? {
+            's' => 1,
+            'm' => 60,
+            'h' => 3_600,
+            'd' => 86_400,
+            _ => return None,
+        };
+        let count: u64 = hint[..hint.len() - 1].parse().ok()?;
+        let secs = count.checked_mul(unit).filter(|secs| *secs <= MAX_RETENTION_SECS)?;
+        Some(Duration::from_secs(secs))
+    }
+
+    /// `ttl` in the largest unit that divides it exactly, e.g. `30d`.
+    fn format_retention(ttl: Duration) -> String {
+        let secs = ttl.as_secs();
+        match [(86_400, 'd'), (3_600, 'h'), (60, 'm')]
+            .into_iter()
+            .find(|(unit, _)| secs > 0 && secs.is_multiple_of(*unit))
+        {
+            Some((unit, suffix)) => format!("{}{suffix}", secs / unit),
+            None => format!("{secs}s"),
+        }
+    }
+
+    /// Deletes the least recently modified log files of `stem` in `dir` until they total
+    /// at most `budget` bytes, sparing `active`. Returns the number of bytes freed.
+    pub(crate) fn enforce_disk_budget(
+        dir: &Path,
+        stem: &str,
+        budget: u64,
+        active: &Path,
+    ) -> io::Result<u64> {
+        let owned = |name: &str| owned_log_file(name, stem);
+        let active = active.file_name();
+        let mut files = Vec::new();
+        let mut total = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() || !owned(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            total += metadata.len();
+            if Some(entry.file_name().as_os_str()) != active {
+                let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+                files.push((modified, metadata.len(), entry.path()));
+            }
+        }
+        files.sort();
+
+        let mut freed = 0;
+        for (_, len, path) in files {
+            if total - freed <= budget {
+                break;
+            }
+            std::fs::remove_file(path)?;
+            freed += len;
+        }
+        Ok(freed)
+    }
+
+    /// `-YYYYMMDD-HHMMSS-PID` for a session started at `start`.
+    pub(crate) fn session_suffix(start: SystemTime, pid: u32) -> String {
+        let digits: String = format_utc(start).chars().filter(char::is_ascii_digit).collect();
+        let (date, time) = digits.split_at(digits.len().saturating_sub(6));
+        format!("-{date}-{time}-{pid}")
+    }
+
+    /// Deletes the log files in `dir` of all but the newest `keep` sessions of `stem`,
+    /// including per-thread and per-tenant files. Returns how many files were deleted.
+    pub(crate) fn prune_sessions(dir: &Path, stem: &str, keep: usize) -> io::Result<usize> {
+        let prefix = format!("{stem}-");
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !owned_log_file(&name, stem) {
+                continue;
+            }
+            let Some(rest) = name.strip_prefix(&prefix) else {
+                continue;
+            };
+            let id = rest.split('.').next().unwrap_or_default();
+            files.push((id.to_owned(), entry.path()));
+        }
+        let mut sessions: Vec<&str> = files.iter().map(|(id, _)| id.as_str()).collect();
+        sessions.sort_unstable_by(|a, b| b.cmp(a));
+        sessions.dedup();
+        let kept: Vec<String> = sessions.iter().take(keep).map(|id| id.to_string()).collect();
+
+        let mut removed = 0;
+        for (id, path) in &files {
+            if !kept.contains(id) {
+                std::fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Joins `values` with `delimiter`. For commas, values containing a comma, quote or
+    /// line break are quoted; for other delimiters, the delimiter and line breaks are
+    /// escaped as `\t`, `\n` and `\r`.
+    fn join_delimited<'a>(values: impl Iterator<Item = Cow<'a, str>>, delimiter: char) -> String {
+        let mut line = String::new();
+        for (index, value) in values.enumerate() {
+            if index > 0 {
+                line.push(delimiter);
+            }
+            if delimiter == ',' {
+                if value.contains([',', '"', '\n', '\r']) {
+                    line.push('"');
+                    line.push_str(&value.replace('"', "\"\""));
+                    line.push('"');
+                } else {
+                    line.push_str(&value);
+                }
+            } else {
+                for c in value.chars() {
+                    match c {
+                        '\\' => line.push_str("\\\\"),
+                        '\n' => line.push_str("\\n"),
+                        '\r' => line.push_str("\\r"),
+                        '\t' => line.push_str("\\t"),
+                        c if c == delimiter => line.push_str(&c.escape_default().to_string()),
+                        c => line.push(c),
+                    }
+                }
+            }
+        }
+        line
+    }
+
+    /// Cuts `text` to at most `width` characters, keeping the end behind a `…`.
+    pub(crate) fn fit_column(text: &str, width: usize) -> Cow<'_, str> {
+        let count = text.chars().count();
+        if count <= width {
+            return Cow::Borrowed(text);
+        }
+        let keep: String = text.chars().skip(count - width.saturating_sub(1)).collect();
+        Cow::Owned(if width == 0 { keep } else { format!("…{keep}") })
+    }
+
+    /// Replaces known markup tags in `text` with ANSI codes (`ansi`) or nothing.
+    /// Unknown tags and stray `<` are kept as written.
+    pub(crate) fn render_markup(text: &str, ansi: bool) -> Cow<'_, str> {
+        if !text.contains('<') {
+            return Cow::Borrowed(text);
+        }
+        let mut rendered = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('<') {
+            rendered.push_str(&rest[..start]);
+            let tail = &rest[start..];
+            let tag = tail.find('>').map(|end| (&tail[1..end], end + 1));
+            match tag.and_then(|(tag, len)| Some((markup_code(tag)?, len))) {
+                Some((code, len)) => {
+                    if ansi {
+                        rendered.push_str(code);
+                    }
+                    rest = &tail[len..];
+                }
+                None => {
+                    rendered.push('<');
+                    rest = &tail[1..];
+                }
+            }
+        }
+        rendered.push_str(rest);
+        Cow::Owned(rendered)
+    }
+
+    fn markup_code(tag: &str) -> Option<&'static str> {
+        let (name, closing) = match tag.strip_prefix('/') {
+            Some(name) => (name, true),
+            None => (tag, false),
+        };
+        let code = match name {
+            "b" => "\x1b[1m",
+            "dim" => "\x1b[2m",
+            "i" => "\x1b[3m",
+            "u" => "\x1b[4m",
+            "red" => Colours::RED,
+            "green" => Colours::GREEN,
+            "yellow" => Colours::YELLOW,
+            "blue" => Colours::BLUE,
+            "cyan" => Colours::CYAN,
+            _ => return None,
+        };
+        Some(if closing { "\x1b[0m" } else { code })
+    }
+
+    /// Whether `value` falls in the sampled `ratio`, using FNV-1a so the decision is the
+    /// same across runs, machines and Rust versions.
+    pub(crate) fn key_sampled(value: &str, ratio: f64) -> bool {
+        let hash = value.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+        ratio >= 1.0 || (hash as f64 / u64::MAX as f64) < ratio
+    }
+
+    /// Combinations of settings that are accepted but can't take effect.
+    pub(crate) fn config_conflicts(
+        output: Output,
+        split_files: bool,
+        routes: bool,
+        console_hook: bool,
+    ) -> Vec<&'static str> {
+        let mut conflicts = Vec::new();
+        let writes_file = matches!(output, Output::ConsoleAndFile | Output::File);
+        let writes_console = matches!(output, Output::ConsoleAndFile | Output::Console);
+        if split_files && !writes_file {
+            conflicts.push("per-thread or per-tenant files are set but output has no file");
+        }
+        if routes && !writes_file {
+            conflicts.push("routes are only written when output includes the file");
+        }
+        if console_hook && !writes_console {
+            conflicts.push("a console hook is set but output has no console");
+        }
+        conflicts
+    }
+
+    /// Removes `prefix` and any following separator from `file`, leaving it unchanged
+    /// if it lies outside `prefix`.
+    pub(crate) fn trim_source_path<'a>(file: &'a str, prefix: &str) -> &'a str {
+        match file.strip_prefix(prefix) {
+            Some(rest)
+                if !prefix.is_empty()
+                    && (prefix.ends_with(['/', '\\']) || rest.starts_with(['/', '\\'])) =>
+            {
+                rest.trim_start_matches(['/', '\\'])
+            }
+            _ => file,
+        }
+    }
+
+    /// Applies `style` (and optionally crate-name stripping) to a module path.
+    /// Returns `None` when the module should not be shown.
+    pub(crate) fn shorten_module(module: &str, style: ModuleStyle, strip_crate: bool) -> Option<String> {
+        let module = match module.split_once("::") {
+            Some((_, rest)) if strip_crate => rest,
+            _ => module,
+        };
+        match style {
+            ModuleStyle::Hidden => None,
+            ModuleStyle::Full => Some(module.to_owned()),
+            ModuleStyle::Dotted => Some(match module.rsplit_once("::") {
+                Some((parents, last)) => format!("{}::{last}", parents.replace("::", ".")),
+                None => module.to_owned(),
+            }),
+            ModuleStyle::LastSegments(count) => {
+                let segments: Vec<&str> = module.split("::").collect();
+                let skip = segments.len().saturating_sub(count.max(1));
+                Some(segments[skip..].join("::"))
+            }
+        }
+    }
+
+    /// Matches `text` against `pattern`, where `*` matches any (possibly empty) run of characters.
+    pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+        let mut parts = pattern.split('*');
+        let first = parts.next().unwrap_or_default();
+        let Some(mut rest) = text.strip_prefix(first) else {
+            return false;
+        };
+        let parts: Vec<&str> = parts.collect();
+        let Some((last, middle)) = parts.split_last() else {
+            return rest.is_empty();
+        };
+        for part in middle {
+            match rest.find(part) {
+                Some(index) => rest = &rest[index + part.len()..],
+                None => return false,
+            }
+        }
+        rest.len() >= last.len() && rest.ends_with(last)
+    }
+
+    /// Rejects `strftime` patterns chrono can't format, such as `"%Y-%Q"`.
+    #[cfg(feature = "chrono")]
+    pub(crate) fn validate_datetime_pattern(pattern: &str) -> Result<(), LoggerError> {
+        let trial = DateTime::<Utc>::from(UNIX_EPOCH).format(pattern);
+        if write!(String::new(), "{trial}").is_err() {
+            return Err(LoggerError::Config {
+                setting: "datetime_format".to_owned(),
+                value: pattern.to_owned(),
+                expected: "a chrono strftime pattern".to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// `first` plus whatever else arrives on `receiver` within `max_latency`, up to
+    /// `max_records` in all.
+    pub(crate) fn collect_batch<T>(
+        receiver: &std::sync::mpsc::Receiver<T>,
+        first: T,
+        max_records: usize,
+        max_latency: Duration,
+    ) -> Vec<T> {
+        let deadline = std::time::Instant::now() + max_latency;
+        let mut batch = vec![first];
+        while batch.len() < max_records {
+            let wait = deadline.saturating_duration_since(std::time::Instant::now());
+            match receiver.recv_timeout(wait) {
+                Ok(item) => batch.push(item),
+                Err(_) => break,
+            }
+        }
+        batch
+    }
+
+    /// Splits a `Logger::set_pattern` layout into its pieces.
+    pub(crate) fn parse_pattern(pattern: &str) -> Result<Vec<PatternPiece>, LoggerError> {
+        let invalid = |expected: &str| LoggerError::Config {
+            setting: "pattern".to_owned(),
+            value: pattern.to_owned(),
+            expected: expected.to_owned(),
+        };
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut rest = pattern;
+        while let Some(start) = rest.find('%') {
+            literal.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+            let (piece, consumed) = if rest.starts_with("msg") {
+                (PatternPiece::Message, 3)
+            } else if rest.starts_with("kv") {
+                (PatternPiece::Fields, 2)
+            } else {
+                let piece = match rest.chars().next() {
+                    Some('%') => {
+                        literal.push('%');
+                        rest = &rest[1..];
+                        continue;
+                    }
+                    Some('d') => PatternPiece::Timestamp(None),
+                    Some('l') => PatternPiece::Level,
+                    Some('m') => PatternPiece::Module,
+                    Some('f') => PatternPiece::File,
+                    Some('L') => PatternPiece::Line,
+                    Some('t') => PatternPiece::Thread,
+                    _ => return Err(invalid("%d, %l, %m, %f, %L, %t, %msg, %kv or %%")),
+                };
+                (piece, 1)
+            };
+            rest = &rest[consumed..];
+
+            let mut piece = piece;
+            if let Some(argument) = rest.strip_prefix('{') {
+                let end = argument.find('}').ok_or_else(|| invalid("a closing }"))?;
+                let argument = &argument[..end];
+                rest = &rest[end + 2..];
+                piece = match piece {
+                    #[cfg(feature = "chrono")]
+                    PatternPiece::Timestamp(None) => {
+                        validate_datetime_pattern(argument)?;
+                        PatternPiece::Timestamp(Some(argument.to_owned()))
+                    }
+                    #[cfg(not(feature = "chrono"))]
+                    PatternPiece::Timestamp(None) => {
+                        return Err(invalid("%d without a format (the chrono feature is off)"));
+                    }
+                    PatternPiece::Module if argument == "target" => PatternPiece::Module,
+                    _ => return Err(invalid("an argument only on %d{..} and %m{target}")),
+                };
+            }
+            if !literal.is_empty() {
+                pieces.push(PatternPiece::Literal(std::mem::take(&mut literal)));
+            }
+            pieces.push(piece);
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            pieces.push(PatternPiece::Literal(literal));
+        }
+        Ok(pieces)
+    }
+
+    /// What is known about the directory of `path`: whether it exists (else its nearest
+    /// existing ancestor) and its permissions.
+    fn file_diagnostics(path: &Path) -> String {
+        let mut notes = Vec::new();
+        let mut dir = path.parent().unwrap_or(Path::new("/"));
+        if dir.exists() {
+            notes.push(format!("directory {}", dir.display()));
+        } else {
+            notes.push(format!("directory {} does not exist", dir.display()));
+            while let Some(parent) = dir.parent().filter(|_| !dir.exists()) {
+                dir = parent;
+            }
+            notes.push(format!("nearest existing directory {}", dir.display()));
+        }
+        match dir.metadata() {
+            #[cfg(unix)]
+            Ok(metadata) => {
+                use std::os::unix::fs::{MetadataExt, PermissionsExt};
+                let mode = metadata.permissions().mode() & 0o7777;
+                notes.push(format!("mode {mode:o}, owner uid {}", metadata.uid()));
+            }
+            #[cfg(not(unix))]
+            Ok(metadata) => {
+                let readonly = metadata.permissions().readonly();
+                notes.push(if readonly { "read-only" } else { "writable" }.to_owned());
+            }
+            Err(err) => notes.push(format!("permissions unknown: {err}")),
+        }
+        notes.join(", ")
+    }
+
+    /// This machine's name for syslog lines, or `-` when it can't be found.
+    pub(crate) fn syslog_hostname() -> &'static str {
+        static HOSTNAME: OnceLock<String> = OnceLock::new();
+        HOSTNAME.get_or_init(|| {
+            let name = env::var("HOSTNAME")
+                .or_else(|_| env::var("COMPUTERNAME"))
+                .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+                .unwrap_or_default();
+            let name: String = name.trim().chars().filter(|c| c.is_ascii_graphic()).collect();
+            if name.is_empty() {
+                "-".to_owned()
+            } else {
+                name
+            }
+        })
+    }
+
+    /// Formats `timestamp` as `YYYY-MM-DD HH:MM:SS` in UTC without pulling in chrono.
+    pub(crate) fn format_utc(timestamp: SystemTime) -> String {
+        let secs = match timestamp.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(err) => -(err.duration().as_secs() as i64),
+        };
+        let secs_of_day = secs.rem_euclid(86_400);
+
+        // Civil-from-days conversion on the proleptic Gregorian calendar.
+        let z = secs.div_euclid(86_400) + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        format!(
+            "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+            secs_of_day / 3_600,
+            secs_of_day % 3_600 / 60,
+            secs_of_day % 60
+        )
+    }
+
+    /// Logging facade so libraries can accept `&dyn FiroLog` and tests can inject fakes.
+    /// Covers the level methods, `flush` and `stats`; only `log_level` is required.
+    pub trait FiroLog {
+        fn log_level(&self, level: LogLevel, args: Arguments);
+
+        /// Pushes out buffered records; does nothing by default.
+        fn flush(&self) {}
+
+        /// Counts of the records logged so far; an empty snapshot by default.
+        fn stats(&self) -> LoggerStats {
+            LoggerStats {
+                taken_at: SystemTime::now(),
+                records: 0,
+                errors: 0,
+                warnings: 0,
+                last_error_at: None,
+                clock_corrections: 0,
+                memory_in_use: 0,
+                memory_dropped: 0,
+                by_level: Vec::new(),
+            }
+        }
+
+        fn log(&self, args: Arguments) {
+            self.log_level(LogLevel::Log, args);
+        }
+
+        fn critical(&self, args: Arguments) {
+            self.log_level(LogLevel::Critical, args);
+        }
+
+        fn error(&self, args: Arguments) {
+            self.log_level(LogLevel::Error, args);
+        }
+
+        fn warning(&self, args: Arguments) {
+            self.log_level(LogLevel::Warning, args);
+        }
+
+        fn debug(&self, args: Arguments) {
+            self.log_level(LogLevel::Debug, args);
+        }
+
+        fn info(&self, args: Arguments) {
+            self.log_level(LogLevel::Info, args);
+        }
+
+        fn success(&self, args: Arguments) {
+            self.log_level(LogLevel::Success, args);
+        }
+    }
+
+    impl FiroLog for Logger {
+        fn log_level(&self, level: LogLevel, args: Arguments) {
+            Logger::log_msg(level, None, args);
+        }
+
+        fn flush(&self) {
+            Logger::flush();
+        }
+
+        fn stats(&self) -> LoggerStats {
+            Logger::stats()
+        }
+    }
+
+    /// A `FiroLog` that discards everything.
+    #[derive(Debug, Default)]
+    pub struct NullLogger;
+
+    impl FiroLog for NullLogger {
+        fn log_level(&self, _level: LogLevel, _args: Arguments) {}
+    }
+
+    #[doc(hidden)]
+    #[macro_export]
+    macro_rules! callsite {
+        () => {
+            $crate::logger::Callsite {
+                module: module_path!(),
+                file: file!(),
+                line: line!(),
+            }
+        };
+    }
+
+    /// Munches the leading `key = value,` pairs of a level macro into fields, e.g.
+    /// `log_info!(user_id = 42, latency_ms = 12.5, "request finished")`. The message may
+    /// be left out: `log_info!(user_id = 42)` logs the fields alone.
+    #[doc(hidden)]
+    #[macro_export]
+    macro_rules! __log_kv {
+        ($level:expr, [$($fields:tt)*] $key:ident = $value:expr $(,)?) => {
+            $crate::logger::Logger::log_with_metadata(
+                $crate::callsite!(),
+                $level,
+                &[$($fields)* (stringify!($key), &$value as &dyn $crate::logger::FieldValue)],
+                format_args!(""),
+            )
+        };
+        ($level:expr, [$($fields:tt)*] $key:ident = $value:expr, $($rest:tt)+) => {
+            $crate::__log_kv!(
+                $level,
+                [$($fields)* (stringify!($key), &$value as &dyn $crate::logger::FieldValue),]
+                $($rest)+
+            )
+        };
+        ($level:expr, [$($fields:tt)*] $($arg:tt)+) => {
+            $crate::logger::Logger::log_with_metadata(
+                $crate::callsite!(),
+                $level,
+                &[$($fields)*],
+                format_args!($($arg)+),
+            )
+        };
+    }
+
+    #[macro_export]
+    macro_rules! log_info {
+        ($key:ident = $($rest:tt)+) => {
+            $crate::__log_kv!($crate::logger::LogLevel::Info, [] $key = $($rest)+)
+        };
+        ($($arg:tt)*) => {
+            $crate::logger::Logger::log_from(
+                $crate::callsite!(),
+                $crate::logger::LogLevel::Info,
+                format_args!($($arg)*),
+            )
+        };
+    }
+
+    #[macro_export]
+    macro_rules! log_debug {
+        ($key:ident = $($rest:tt)+) => {
+            $crate::__log_kv!($crate::logger::LogLevel::Debug, [] $key = $($rest)+)
+        };
+        ($($arg:tt)*) => {
+            $crate::logger::Logger::log_from(
+                $crate::callsite!(),
+                $crate::logger::LogLevel::Debug,
+                format_args!($($arg)*),
+            )
+        };
+    }
+
+    #[macro_export]
+    macro_rules! log_warning {
+        ($key:ident = $($rest:tt)+) => {
+            $crate::__log_kv!($crate::logger::LogLevel::Warning, [] $key = $($rest)+)
+        };
+        ($($arg:tt)*) => {
+            $crate::logger::Logger::log_from(
+                $crate::callsite!(),
+                $crate::logger::LogLevel::Warning,
+                format_args!($($arg)*),
+            )
+        };
+    }
+
+    #[macro_export]
+    macro_rules! log_success {
+        ($key:ident = $($rest:tt)+) => {
+            $crate::__log_kv!($crate::logger::LogLevel::Success, [] $key = $($rest)+)
+        };
+        ($($arg:tt)*) => {
+            $crate::logger::Logger::log_from(
+                $crate::callsite!(),
+                $crate::logger::LogLevel::Success,
+                format_args!($($arg)*),
+            )
+        };
+    }
+
+    #[macro_export]
+    macro_rules! log_error {
+        ($key:ident = $($rest:tt)+) => {
+            $crate::__log_kv!($crate::logger::LogLevel::Error, [] $key = $($rest)+)
+        };
+        ($($arg:tt)*) => {
+            $crate::logger::Logger::log_from(
+                $crate::callsite!(),
+                $crate::logger::LogLevel::Error,
+                format_args!($($arg)*),
+            )
+        };
+    }
+
+    #[macro_export]
+    macro_rules! log_critical {
+        ($key:ident = $($rest:tt)+) => {
+            $crate::__log_kv!($crate::logger::LogLevel::Critical, [] $key = $($rest)+)
+        };
+        ($($arg:tt)*) => {
+            $crate::logger::Logger::log_from(
+                $crate::callsite!(),
+                $crate::logger::LogLevel::Critical,
+                format_args!($($arg)*),
+            )
+        };
+    }
+
+    /// Logs an HTTP access record from named `HttpAccess` fields; unset fields keep their defaults:
+    /// `http_log!(method = "GET", path = "/users", status = 200, latency_ms = 12.5)`.
+    #[macro_export]
+    macro_rules! http_log {
+        ($($field:ident = $value:expr),+ $(,)?) => {{
+            #[allow(clippy::needless_update)]
+            let access = $crate::logger::HttpAccess {
+                $($field: $value,)+
+                ..::std::default::Default::default()
+            };
+            $crate::logger::Logger::log_http($crate::callsite!(), &access)
+        }};
+    }
+
+    /// Runs a block and records how long it took under `name`:
+    /// `let rows = time_block!("db.query", { run_query() });`
+    #[macro_export]
+    macro_rules! time_block {
+        ($name:expr, $body:block) => {{
+            let start = ::std::time::Instant::now();
+            let result = $body;
+            $crate::logger::Logger::record_duration($name, start.elapsed());
+            result
+        }};
+    }
+
+    /// Logs untrusted bytes without corrupting output: `log_bytes!(LogLevel::Warning, "payload", data)`.
+    /// Invalid UTF-8 becomes `\xNN` and control characters are escaped.
+    #[macro_export]
+    macro_rules! log_bytes {
+        ($level:expr, $label:expr, $bytes:expr) => {
+            $crate::logger::Logger::log_from(
+                $crate::callsite!(),
+                $level,
+                format_args!(
+                    "{}: {}",
+                    $label,
+                    $crate::logger::EscapedBytes(::std::convert::AsRef::<[u8]>::as_ref(&$bytes))
+                ),
+            )
+        };
+    }
+
+    /// Logs at a level added with `Logger::register_level`:
+    /// `log_custom!("AUDIT", "{user} signed in")`.
+    #[macro_export]
+    macro_rules! log_custom {
+        ($name:expr, $($arg:tt)*) => {
+            $crate::logger::Logger::log_custom(
+                $crate::callsite!(),
+                $name,
+                format_args!($($arg)*),
+            )
+        };
+    }
+
+    /// Logs without blocking, returning `Err(WouldBlock)` when the writer is behind:
+    /// `if try_log!(LogLevel::Info, "tick {n}").is_err() { dropped += 1 }`.
+    #[macro_export]
+    macro_rules! try_log {
+        ($level:expr, $($arg:tt)*) => {
+            $crate::logger::Logger::try_log(
+                ::std::option::Option::Some($crate::callsite!()),
+                $level,
+                format_args!($($arg)*),
+            )
+        };
+    }
+
+    /// Logs with extra fields, rendered after the message:
+    /// `log_with_metadata!(LogLevel::Info, { "user" => name, "attempts" => n }, "login")`.
+    ///
+    /// Values can be any `Display` expression or a `field_duration`/`field_bytes` measure;
+    /// they are evaluated at the call site but only formatted when the record passes the
+    /// level filters.
+    #[macro_export]
+    macro_rules! log_with_metadata {
+        ($level:expr, { $($key:expr => $value:expr),* $(,)? }, $($arg:tt)*) => {
+            $crate::logger::Logger::log_with_metadata(
+                $crate::callsite!(),
+                $level,
+                &[$(($key, &$value as &dyn $crate::logger::FieldValue)),*],
+                format_args!($($arg)*),
+            )
+        };
+    }
+
+    /// Warns once per feature key that it is deprecated, for libraries built on this crate:
+    /// `warn_deprecated!(feature = "Client::connect_v1", since = "1.2", note = "use connect")`.
+    #[macro_export]
+    macro_rules! warn_deprecated {
+        (feature = $feature:expr, since = $since:expr $(, note = $note:expr)? $(,)?) => {
+            $crate::logger::Logger::warn_deprecated(
+                $crate::callsite!(),
+                $feature,
+                $since,
+                $crate::warn_deprecated!(@note $($note)?),
+            )
+        };
+        (@note $note:expr) => {
+            $note
+        };
+        (@note) => {
+            ""
+        };
+    }
+
+    /// Logs a catalogued event: `log_event!(E1042, user = name, path = "/admin")`.
+    #[macro_export]
+    macro_rules! log_event {
+        ($code:ident $(, $key:ident = $value:expr)* $(,)?) => {
+            $crate::logger::Logger::log_event(
+                $crate::callsite!(),
+                stringify!($code),
+                &[$((stringify!($key), &$value as &dyn ::std::fmt::Display)),*],
+            )
+        };
+    }
+}
+
+/// Offline tools for log files written by this crate.
+pub mod privacy {
+    use crate::logger::EMIT_LOCK;
+    use std::fmt;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    /// What to do with records that mention a subject.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum ForgetAction {
+        /// Drop the whole record.
+        Remove,
+        /// Keep the record but replace the identifying value with `[REDACTED]`.
+        Redact,
+    }
+
+    /// Audit summary of a `forget_subject` run.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ForgetSummary {
+        pub path: PathBuf,
+        pub field: String,
+        pub action: ForgetAction,
+        pub records_scanned: usize,
+        pub records_matched: usize,
+    }
+
+    impl fmt::Display for ForgetSummary {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let action = match self.action {
+                ForgetAction::Remove => "removed",
+                ForgetAction::Redact => "redacted",
+            };
+            write!(
+                f,
+                "{}: {} of {} records {action} for field {}",
+                self.path.display(),
+                self.records_matched,
+                self.records_scanned,
+                self.field
+            )
+        }
+    }
+
+    /// Removes or redacts every record in `path` carrying the field `key=value`.
+    ///
+    /// The file is rewritten through a temporary file and a rename, so readers never see
+    /// a half-written log. This process's logging waits meanwhile, and lines other
+    /// processes append while the file is filtered are filtered too and carried over.
+    /// Nothing is written when no record matches.
+    pub fn forget_subject(
+        path: &Path,
+        key: &str,
+        value: &str,
+        action: ForgetAction,
+    ) -> io::Result<ForgetSummary> {
+        let _emit = EMIT_LOCK.write().unwrap();
+        let contents = fs::read_to_string(path)?;
+        let needle = format!(" {key}={value}");
+        let mut summary = ForgetSummary {
+            path: path.to_owned(),
+            field: key.to_owned(),
+            action,
+            records_scanned: 0,
+            records_matched: 0,
+        };
+
+        let mut output = String::with_capacity(contents.len());
+        forget_lines(&contents, key, &needle, &mut output, &mut summary);
+        if summary.records_matched > 0 {
+            let current = fs::read(path)?;
+            let appended = current.get(contents.len()..).unwrap_or_default();
+            let appended = String::from_utf8_lossy(appended);
+            forget_lines(&appended, key, &needle, &mut output, &mut summary);
+            replace_file(path, ".forget-tmp", output)?;
+        }
+        Ok(summary)
+    }
+
+    /// Copies the lines of `text` to `output`, less those carrying `needle` (` key=value`)
+    /// or with it redacted, as `summary.action` says.
+    fn forget_lines(
+        text: &str,
+        key: &str,
+        needle: &str,
+        output: &mut String,
+        summary: &mut ForgetSummary,
+    ) {
+        for line in text.lines() {
+            summary.records_scanned += 1;
+            let positions = field_positions(line, needle);
+            if positions.is_empty() {
+                output.push_str(line);
+                output.push('\n');
+                continue;
+            }
+            summary.records_matched += 1;
+            if summary.action == ForgetAction::Redact {
+                let mut redacted = String::with_capacity(line.len());
+                let mut last = 0;
+                for start in positions {
+                    redacted.push_str(&line[last..start]);
+                    redacted.push_str(&format!(" {key}=[REDACTED]"));
+                    last = start + needle.len();
+                }
+                redacted.push_str(&line[last..]);
+                output.push_str(&redacted);
+                output.push('\n');
+            }
+        }
+    }
+
+    /// Replaces `path` with `contents` through a temporary file named with `suffix`.
+    fn replace_file(path: &Path, suffix: &str, contents: String) -> io::Result<()> {
+        let mut temp_name = path.as_os_str().to_owned();
+        temp_name.push(suffix);
+        let temp_path = PathBuf::from(temp_name);
+        fs::write(&temp_path, contents)?;
+        fs::rename(&temp_path, path)
+    }
+
+    /// Outcome of an `expire_records` run.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ExpireSummary {
+        pub path: PathBuf,
+        pub records_scanned: usize,
+        pub records_removed: usize,
+        /// When the next of the remaining records expires, if any will.
+        pub next_expiry: Option<SystemTime>,
+    }
+
+    /// Removes every record in `path` whose `expires` field (`expires=<unix seconds>` in
+    /// text, `"expires":"<unix seconds>"` in JSON lines) is at or before `now`. Rewrites
+    /// the file like `forget_subject`, and only when something expired; lines appended
+    /// while it was being filtered are carried over.
+    pub fn expire_records(path: &Path, now: SystemTime) -> io::Result<ExpireSummary> {
+        let contents = fs::read_to_string(path)?;
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut summary = ExpireSummary {
+            path: path.to_owned(),
+            records_scanned: 0,
+            records_removed: 0,
+            next_expiry: None,
+        };
+
+        let mut output = String::with_capacity(contents.len());
+        for line in contents.lines() {
+            summary.records_scanned += 1;
+            let expires = expires_field(line);
+            match expires {
+                Some(expires) if expires <= now => summary.records_removed += 1,
+                _ => {
+                    if let Some(expires) = expires {
+                        let expires = UNIX_EPOCH + Duration::from_secs(expires);
+                        let next = summary.next_expiry.map_or(expires, |next| next.min(expires));
+                        summary.next_expiry = Some(next);
+                    }
+                    output.push_str(line);
+                    output.push('\n');
+                }
+            }
+        }
+
+        if summary.records_removed > 0 {
+            let appended = fs::read(path)?;
+            output.push_str(&String::from_utf8_lossy(
+                appended.get(contents.len()..).unwrap_or_default(),
+            ));
+            replace_file(path, ".expire-tmp", output)?;
+        }
+        Ok(summary)
+    }
+
+    /// The earliest `expires` value on `line`, as written by the text and pattern
+    /// layouts (`expires=1700000000`), syslog (`expires="1700000000"`) and JSON
+    /// (`"expires":"1700000000"`).
+    fn expires_field(line: &str) -> Option<u64> {
+        [" expires=", "\"expires\":"]
+            .into_iter()
+            .flat_map(|needle| {
+                line.match_indices(needle).filter_map(move |(start, _)| {
+                    let value = line[start + needle.len()..].trim_start_matches('"');
+                    let end = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+                    value[..end].parse::<u64>().ok()
+                })
+            })
+            .min()
+    }
+
+    /// Byte offsets of `needle` in `line` where it forms a whole `key=value` field.
+    fn field_positions(line: &str, needle: &str) -> Vec<usize> {
+        line.match_indices(needle)
+            .filter(|(start, _)| {
+                let end = start + needle.len();
+                end == line.len() || line[end..].starts_with(' ')
+            })
+            .map(|(start, _)| start)
+            .collect()
+    }
+}
+
+/// Standalone HTML reports of a run's records, for CI artifacts and support tickets.
+///
+/// Records can be collected with a filter that keeps a copy of each one, e.g.
+/// `Logger::add_filter(move |record| { sink.lock().unwrap().push(record.clone()); true })`.
+pub mod report {
+    use crate::logger::{LogRecord, Logger};
+    use std::fmt::Write as _;
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    const STYLE: &str = "body{font:14px sans-serif;margin:1em}\
+        table{border-collapse:collapse;width:100%}\
+        td,th{padding:2px 8px;text-align:left;vertical-align:top;border-bottom:1px solid #ddd}\
+        td.message{white-space:pre-wrap;font-family:monospace}\
+        .CRITICAL{color:#fff;background:#c00}\
+        .ERROR{color:#c00}.WARNING{color:#b60}.SUCCESS{color:#080}\
+        .INFO{color:#068}.DEBUG{color:#00a}.LOG{color:#555}";
+
+    const SCRIPT: &str = "function applyFilter(){\
+        const text=document.getElementById('text').value.toLowerCase();\
+        const level=document.getElementById('level').value;\
+        for(const row of document.querySelectorAll('tbody tr')){\
+        const shown=(!level||row.dataset.level===level)&&row.textContent.toLowerCase().includes(text);\
+        row.style.display=shown?'':'none';}}";
+
+    /// Renders `records` as a self-contained HTML page with a text and level filter.
+    /// Fields of each record are shown in a collapsible block.
+    pub fn html(title: &str, records: &[LogRecord]) -> String {
+        let mut page = format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title>\
+             <style>{STYLE}</style><script>{SCRIPT}</script></head><body>\n<h1>{0}</h1>\n\
+             <p><input id=\"text\" placeholder=\"Filter\" oninput=\"applyFilter()\"> \
+             <select id=\"level\" onchange=\"applyFilter()\"><option value=\"\">All levels</option>",
+            escape(title)
+        );
+        let built_in = ["CRITICAL", "ERROR", "WARNING", "SUCCESS", "INFO", "LOG", "DEBUG"];
+        for level in built_in.into_iter().chain(Logger::custom_level_names()) {
+            let _ = write!(page, "<option>{level}</option>");
+        }
+        page.push_str(
+            "</select></p>\n<table><thead><tr><th>Time</th><th>Level</th><th>Module</th>\
+             <th>Message</th></tr></thead><tbody>\n",
+        );
+        for record in records {
+            let level = record.level.as_str();
+            let module = record.callsite.map_or("", |callsite| callsite.module);
+            let _ = write!(
+                page,
+                "<tr data-level=\"{level}\"><td>{}</td><td class=\"{level}\">{level}</td>\
+                 <td>{}</td><td class=\"message\">{}",
+                escape(&Logger::format_timestamp(record.timestamp)),
+                escape(module),
+                escape(&record.message)
+            );
+            let fields = Logger::record_fields(record);
+            if !fields.is_empty() {
+                let _ = write!(page, "<details><summary>{} fields</summary>", fields.len());
+                for (key, value) in fields {
+                    let _ = write!(page, "{}={}<br>", escape(key), escape(&value));
+                }
+                page.push_str("</details>");
+            }
+            page.push_str("</td></tr>\n");
+        }
+        page.push_str("</tbody></table>\n</body></html>\n");
+        page
+    }
+
+    /// Writes `html(title, records)` to `path`.
+    pub fn write_html(path: &Path, title: &str, records: &[LogRecord]) -> io::Result<()> {
+        fs::write(path, html(title, records))
+    }
+
+    fn escape(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&#39;"),
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+}
+
+/// Forwarding records between processes over TCP, for a sidecar or aggregator setup.
+///
+/// Each record travels as one tab-separated line: timestamp in Unix nanoseconds, level,
+/// module, file, line, the message and then one `key=value` column per field.
+pub mod remote {
+    use crate::logger::{
+        json_string, release_memory, reserve_memory, Callsite, FieldStyle, LogLevel, LogRecord,
+        Logger, LoggerError, OutputFormat,
+    };
+    use std::collections::{BTreeMap, HashSet, VecDeque};
+    use std::io::Read;
+    use std::io::{self, BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{mpsc, Arc, Mutex, OnceLock};
+    use std::thread;
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    /// Client side: sends records to a server started with `serve`. Lines are written by
+    /// a background thread from a bounded queue, so a stalled server never holds up
+    /// logging: when the queue is full new lines are dropped and counted. A failed or
+    /// timed-out write goes to the error handler and closes the writer.
+    #[derive(Debug)]
+    pub struct RemoteWriter {
+        sender: mpsc::SyncSender<RemoteMessage>,
+        dropped: Arc<AtomicU64>,
+    }
+
+    /// Lines a `RemoteWriter` keeps while the server is slow.
+    const REMOTE_QUEUE_CAPACITY: usize = 10_000;
+
+    #[derive(Debug)]
+    enum RemoteMessage {
+        Line(String),
+        /// Answered once every line queued before it has been written.
+        Flush(mpsc::Sender<()>),
+    }
+
+    impl RemoteWriter {
+        /// Connects to `addr`, giving up after 5 seconds; writes time out likewise.
+        pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+            Self::connect_timeout(addr, Duration::from_secs(5))
+        }
+
+        /// Like `connect`, with `timeout` for connecting and for each write.
+        pub fn connect_timeout<A: ToSocketAddrs>(addr: A, timeout: Duration) -> io::Result<Self> {
+            let mut last_error = None;
+            let mut connected = None;
+            for addr in addr.to_socket_addrs()? {
+                match TcpStream::connect_timeout(&addr, timeout) {
+                    Ok(stream) => {
+                        connected = Some(stream);
+                        break;
+                    }
+                    Err(err) => last_error = Some(err),
+                }
+            }
+            let Some(mut stream) = connected else {
+                return Err(last_error.unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "address has no socket address")
+                }));
+            };
+            stream.set_write_timeout(Some(timeout))?;
+            let (sender, receiver) = mpsc::sync_channel(REMOTE_QUEUE_CAPACITY);
+            thread::Builder::new()
+                .name("firo-remote-writer".to_owned())
+                .spawn(move || {
+                    for message in receiver {
+                        match message {
+                            RemoteMessage::Line(line) => {
+                                let written = stream.write_all(line.as_bytes());
+                                release_memory(line.len());
+                                if let Err(err) = written {
+                                    // Dropping the receiver closes the writer for `send`.
+                                    Logger::report_error(LoggerError::RemoteWrite(err));
+                                    return;
+                                }
+                            }
+                            RemoteMessage::Flush(done) => {
+                                let _ = done.send(());
+                            }
+                        }
+                    }
+                })?;
+            Ok(RemoteWriter {
+                sender,
+                dropped: Arc::new(AtomicU64::new(0)),
+            })
+        }
+
+        /// Queues `record` for the server. Errors only once the writer has closed after a
+        /// failed write.
+        pub fn send(&self, record: &LogRecord) -> io::Result<()> {
+            let line = encode(record);
+            let size = line.len();
+            if !reserve_memory(size) {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            match self.sender.try_send(RemoteMessage::Line(line)) {
+                Ok(()) => Ok(()),
+                Err(mpsc::TrySendError::Full(_)) => {
+                    release_memory(size);
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    Ok(())
+                }
+                Err(mpsc::TrySendError::Disconnected(_)) => {
+                    release_memory(size);
+                    Err(io::Error::new(io::ErrorKind::BrokenPipe, "remote writer closed"))
+                }
+            }
+        }
+
+        /// Lines dropped because the queue or the memory budget was full.
+        pub fn dropped(&self) -> u64 {
+            self.dropped.load(Ordering::Relaxed)
+        }
+
+        /// Waits until the lines queued so far have been written.
+        pub fn flush(&self) -> io::Result<()> {
+            let closed = || io::Error::new(io::ErrorKind::BrokenPipe, "remote writer closed");
+            let (done, written) = mpsc::channel();
+            self.sender.send(RemoteMessage::Flush(done)).map_err(|_| closed())?;
+            written.recv().map_err(|_| closed())
+        }
+    }
+
+    /// Streams records as newline-delimited lines (JSON by default) to a TCP input such
+    /// as logstash's or vector's. Connects on the first record and reconnects after
+    /// failures, at most once per retry interval; meanwhile lines wait in a bounded
+    /// queue, the oldest being dropped when it is full.
+    #[derive(Debug)]
+    pub struct TcpWriter {
+        addr: String,
+        format: OutputFormat,
+        connect_timeout: Duration,
+        retry_interval: Duration,
+        queue_capacity: usize,
+        state: Mutex<TcpState>,
+    }
+
+    #[derive(Debug, Default)]
+    struct TcpState {
+        stream: Option<TcpStream>,
+        queue: VecDeque<String>,
+        next_attempt: Option<Instant>,
+        dropped: u64,
+    }
+
+    impl TcpWriter {
+        /// A writer for `addr` (`host:port`). Nothing is connected until the first record.
+        pub fn new(addr: impl Into<String>) -> Self {
+            TcpWriter {
+                addr: addr.into(),
+                format: OutputFormat::Json { pretty: false },
+                connect_timeout: Duration::from_secs(5),
+                retry_interval: Duration::from_secs(1),
+                queue_capacity: 10_000,
+                state: Mutex::new(TcpState::default()),
+            }
+        }
+
+        /// The line layout. Multi-line layouts such as `Pretty` will be split by the
+        /// receiver.
+        pub fn format(mut self, format: OutputFormat) -> Self {
+            self.format = format;
+            self
+        }
+
+        /// How long connecting, and then each write, may take; 5 seconds by default. A
+        /// write that times out counts as a failed write.
+        pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+            self.connect_timeout = timeout;
+            self
+        }
+
+        /// How long to wait after a failed connection or write before connecting again.
+        pub fn retry_interval(mut self, interval: Duration) -> Self {
+            self.retry_interval = interval;
+            self
+        }
+
+        /// How many lines are kept while disconnected.
+        pub fn queue_capacity(mut self, capacity: usize) -> Self {
+            self.queue_capacity = capacity;
+            self
+        }
+
+        /// Writes `record`, or queues it while disconnected. Only a failed connection
+        /// attempt is an error, so a down receiver is reported once per retry interval.
+        pub fn send(&self, record: &LogRecord) -> io::Result<()> {
+            let mut line = Logger::format_as(record, self.format, false, FieldStyle::default());
+            line.push('\n');
+            let mut state = self.state.lock().unwrap();
+            if state.queue.len() >= self.queue_capacity.max(1) {
+                if let Some(oldest) = state.queue.pop_front() {
+                    release_memory(oldest.len());
+                }
+                state.dropped += 1;
+            }
+            if reserve_memory(line.len()) {
+                state.queue.push_back(line);
+            } else {
+                state.dropped += 1;
+            }
+
+            if state.stream.is_none() {
+                if state.next_attempt.is_some_and(|at| Instant::now() < at) {
+                    return Ok(());
+                }
+                match self.connect() {
+                    Ok(stream) => state.stream = Some(stream),
+                    Err(err) => {
+                        state.next_attempt = Some(Instant::now() + self.retry_interval);
+                        return Err(err);
+                    }
+                }
+            }
+            self.drain(&mut state);
+            Ok(())
+        }
+
+        /// Writes queued lines in order until the queue is empty or a write fails or times
+        /// out, which drops the connection and keeps the unwritten line queued.
+        fn drain(&self, state: &mut TcpState) {
+            while let Some(line) = state.queue.front() {
+                let Some(stream) = state.stream.as_mut() else {
+                    return;
+                };
+                if stream.write_all(line.as_bytes()).is_err() {
+                    state.stream = None;
+                    state.next_attempt = Some(Instant::now() + self.retry_interval);
+                    return;
+                }
+                release_memory(line.len());
+                state.queue.pop_front();
+            }
+        }
+
+        fn connect(&self) -> io::Result<TcpStream> {
+            let mut last_error = None;
+            for addr in self.addr.to_socket_addrs()? {
+                match TcpStream::connect_timeout(&addr, self.connect_timeout) {
+                    Ok(stream) => {
+                        stream.set_write_timeout(Some(self.connect_timeout))?;
+                        return Ok(stream);
+                    }
+                    Err(err) => last_error = Some(err),
+                }
+            }
+            Err(last_error.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", self.addr))
+            }))
+        }
+
+        /// Lines waiting for a connection.
+        pub fn queued(&self) -> usize {
+            self.state.lock().unwrap().queue.len()
+        }
+
+        /// Lines dropped because the queue or the memory budget was full.
+        pub fn dropped(&self) -> u64 {
+            self.state.lock().unwrap().dropped
+        }
+
+        pub fn flush(&self) -> io::Result<()> {
+            match self.state.lock().unwrap().stream.as_mut() {
+                Some(stream) => stream.flush(),
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl Drop for TcpWriter {
+        fn drop(&mut self) {
+            let state = self.state.get_mut().unwrap_or_else(|err| err.into_inner());
+            release_memory(state.queue.iter().map(String::len).sum());
+        }
+    }
+
+    /// Pushes records to Grafana Loki's HTTP push API (`/loki/api/v1/push`), plain HTTP
+    /// only. Each record goes to the stream named by its labels: `level` (lowercase),
+    /// the labels set with `label`, `module` when enabled, and any fields promoted with
+    /// `field_label`. Records are batched and pushed once `batch_size` are waiting or on
+    /// `flush`; entries of a stream are sent in timestamp order, and an entry older than
+    /// one already pushed to its stream is sent with that entry's timestamp, since Loki
+    /// rejects out-of-order entries.
+    ///
+    /// Full batches are pushed by a background thread, so a slow or unreachable Loki never
+    /// holds up logging; failed pushes go to the error handler, and batches are dropped
+    /// while a few are already waiting to be pushed. Dropping the writer pushes the full
+    /// batches but not the last partial one: `flush` (`Logger::flush` for the installed
+    /// writer) is the only way to push that.
+    #[derive(Debug)]
+    pub struct LokiWriter {
+        /// `host:port` from the URL.
+        host: String,
+        path: String,
+        format: OutputFormat,
+        labels: Vec<(String, String)>,
+        module_label: bool,
+        field_labels: Vec<String>,
+        batch_size: usize,
+        timeout: Duration,
+        state: Mutex<LokiState>,
+        /// Started with the first push; `None` if the thread could not be spawned.
+        pusher: OnceLock<Option<mpsc::SyncSender<LokiPush>>>,
+    }
+
+    /// How many batches may wait for the pusher thread before new ones are dropped.
+    const LOKI_QUEUED_BATCHES: usize = 4;
+
+    /// Work for the pusher thread: a push body, and where to report its outcome instead of
+    /// the error handler. A push without a body just reports once the earlier ones are done.
+    #[derive(Debug)]
+    struct LokiPush {
+        body: Option<String>,
+        done: Option<mpsc::Sender<io::Result<()>>>,
+    }
+
+    #[derive(Debug, Default)]
+    struct LokiState {
+        /// Waiting entries (nanoseconds, line) by stream labels, rendered as JSON.
+        streams: BTreeMap<String, Vec<(u128, String)>>,
+        pending: usize,
+        /// The newest timestamp pushed to each stream.
+        latest: BTreeMap<String, u128>,
+    }
+
+    impl LokiWriter {
+        /// A writer for the Loki at `url`, e.g. `http://localhost:3100`.
+        pub fn new(url: &str) -> io::Result<Self> {
+            let rest = url.strip_prefix("http://").ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "only http:// Loki URLs are supported")
+            })?;
+            let (host, prefix) = match rest.split_once('/') {
+                Some((host, prefix)) => (host, format!("/{}", prefix.trim_end_matches('/'))),
+                None => (rest, String::new()),
+            };
+            if host.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Loki URL has no host"));
+            }
+            Ok(LokiWriter {
+                host: with_default_port(host)?,
+                path: format!("{}/loki/api/v1/push", prefix.trim_end_matches('/')),
+                format: OutputFormat::Json { pretty: false },
+                labels: Vec::new(),
+                module_label: false,
+                field_labels: Vec::new(),
+                batch_size: 100,
+                timeout: Duration::from_secs(10),
+                state: Mutex::new(LokiState::default()),
+                pusher: OnceLock::new(),
+            })
+        }
+
+        /// The line layout; JSON by default.
+        pub fn format(mut self, format: OutputFormat) -> Self {
+            self.format = format;
+            self
+        }
+
+        /// A label every stream gets, such as `job` or `env`.
+        pub fn label(mut self, name: &str, value: &str) -> Self {
+            self.labels.push((name.to_owned(), value.to_owned()));
+            self
+        }
+
+        /// Adds the caller's module path as a `module` label. Modules are few, but each
+        /// one is its own stream.
+        pub fn module_label(mut self, enabled: bool) -> Self {
+            self.module_label = enabled;
+            self
+        }
+
+        /// Promotes the field `key` to a label on records that have it. Keep this to
+        /// low-cardinality fields such as `tenant_id` or `region`.
+        pub fn field_label(mut self, key: &str) -> Self {
+            self.field_labels.push(key.to_owned());
+            self
+        }
+
+        /// How many records are collected before a push.
+        pub fn batch_size(mut self, records: usize) -> Self {
+            self.batch_size = records.max(1);
+            self
+        }
+
+        /// How long a push may take to connect, send its body and get an answer, each;
+        /// 10 seconds by default.
+        pub fn timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = timeout;
+            self
+        }
+
+        /// The labels of the stream `record` belongs to, as a JSON object.
+        pub(crate) fn stream_labels(&self, record: &LogRecord) -> String {
+            let mut labels = BTreeMap::new();
+            for (name, value) in &self.labels {
+                labels.insert(name.as_str(), value.clone());
+            }
+            labels.insert("level", record.level.as_str().to_lowercase());
+            if let (true, Some(callsite)) = (self.module_label, record.callsite) {
+                labels.insert("module", callsite.module.to_owned());
+            }
+            for key in &self.field_labels {
+                if let Some(value) = record.field(key) {
+                    labels.insert(key.as_str(), value.to_owned());
+                }
+            }
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(name, value)| format!("{}:{}", json_string(name), json_string(value)))
+                .collect();
+            format!("{{{}}}", labels.join(","))
+        }
+
+        /// Adds `record` to the batch, handing the batch to the pusher thread once it is
+        /// full.
+        pub fn send(&self, record: &LogRecord) -> io::Result<()> {
+            let nanos = record
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            let line = Logger::format_as(record, self.format, false, FieldStyle::default());
+            let mut state = self.state.lock().unwrap();
+            state
+                .streams
+                .entry(self.stream_labels(record))
+                .or_default()
+                .push((nanos, line));
+            state.pending += 1;
+            if state.pending >= self.batch_size {
+                self.push(&mut state)?;
+            }
+            Ok(())
+        }
+
+        /// Pushes the waiting records now, and waits until they and the batches before
+        /// them have been pushed. Returns the first push error among them.
+        pub fn flush(&self) -> io::Result<()> {
+            let queued = {
+                let mut state = self.state.lock().unwrap();
+                if state.pending > 0 {
+                    self.push(&mut state)?;
+                }
+                self.pusher.get().cloned().flatten()
+            };
+            let Some(pusher) = queued else {
+                return Ok(());
+            };
+            let (done, outcome) = mpsc::channel();
+            let stopped = || io::Error::other("the Loki pusher thread stopped");
+            let push = LokiPush {
+                body: None,
+                done: Some(done),
+            };
+            pusher.send(push).map_err(|_| stopped())?;
+            outcome.iter().find(Result::is_err).unwrap_or(Ok(()))
+        }
+
+        /// Hands the batch to the pusher thread. It is dropped when the push fails, or
+        /// when the pusher is too far behind, so a Loki outage can't grow it without bound.
+        fn push(&self, state: &mut LokiState) -> io::Result<()> {
+            let streams = std::mem::take(&mut state.streams);
+            state.pending = 0;
+            let body = Self::payload(streams, &mut state.latest);
+            let size = body.len();
+            if !reserve_memory(size) {
+                return Err(io::Error::other("memory budget exhausted; Loki batch dropped"));
+            }
+            let push = LokiPush {
+                body: Some(body),
+                done: None,
+            };
+            let queued = match self.pusher() {
+                Some(pusher) => pusher.try_send(push).is_ok(),
+                None => false,
+            };
+            if queued {
+                Ok(())
+            } else {
+                release_memory(size);
+                Err(io::Error::other("Loki pushes are backed up; batch dropped"))
+            }
+        }
+
+        fn pusher(&self) -> Option<&mpsc::SyncSender<LokiPush>> {
+            self.pusher
+                .get_or_init(|| {
+                    let (sender, receiver) = mpsc::sync_channel(LOKI_QUEUED_BATCHES);
+                    let (host, path) = (self.host.clone(), self.path.clone());
+                    let timeout = self.timeout;
+                    thread::Builder::new()
+                        .name("firo-loki".to_owned())
+                        .spawn(move || Self::run_pusher(&receiver, &host, &path, timeout))
+                        .ok()
+                        .map(|_| sender)
+                })
+                .as_ref()
+        }
+
+        /// The pusher thread: posts bodies in order until the writer is dropped. Errors are
+        /// kept for the next `flush` waiting on it, or else go to the error handler.
+        fn run_pusher(
+            receiver: &mpsc::Receiver<LokiPush>,
+            host: &str,
+            path: &str,
+            timeout: Duration,
+        ) {
+            let mut unreported = Vec::new();
+            for push in receiver {
+                if let Some(body) = push.body {
+                    let result = Self::post(host, path, timeout, &body);
+                    release_memory(body.len());
+                    unreported.extend(result.err());
+                }
+                match push.done {
+                    Some(done) => {
+                        for err in unreported.drain(..) {
+                            let _ = done.send(Err(err));
+                        }
+                    }
+                    None => {
+                        for err in unreported.drain(..) {
+                            Logger::report_error(LoggerError::RemoteWrite(err));
+                        }
+                    }
+                }
+            }
+        }
+
+        /// The push API body. Sorts each stream's entries and keeps them from going back
+        /// in time relative to `latest`, which is updated.
+        pub(crate) fn payload(
+            streams: BTreeMap<String, Vec<(u128, String)>>,
+            latest: &mut BTreeMap<String, u128>,
+        ) -> String {
+            let mut body = String::from("{\"streams\":[");
+            for (index, (labels, mut entries)) in streams.into_iter().enumerate() {
+                entries.sort_by_key(|(nanos, _)| *nanos);
+                let floor = latest.get(&labels).copied().unwrap_or(0);
+                if index > 0 {
+                    body.push(',');
+                }
+                body.push_str(&format!("{{\"stream\":{labels},\"values\":["));
+                let mut newest = floor;
+                for (position, (nanos, line)) in entries.iter().enumerate() {
+                    newest = newest.max(*nanos);
+                    if position > 0 {
+                        body.push(',');
+                    }
+                    let nanos = json_string(&(*nanos).max(floor).to_string());
+                    body.push_str(&format!("[{nanos},{}]", json_string(line)));
+                }
+                body.push_str("]}");
+                latest.insert(labels, newest);
+            }
+            body.push_str("]}");
+            body
+        }
+
+        fn post(host: &str, path: &str, timeout: Duration, body: &str) -> io::Result<()> {
+            let addr = host.to_socket_addrs()?.next().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("{host} did not resolve"))
+            })?;
+            let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+            stream.set_write_timeout(Some(timeout))?;
+            stream.set_read_timeout(Some(timeout))?;
+            write!(
+                stream,
+                "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            let status = response.split(' ').nth(1).unwrap_or_default();
+            if status.starts_with('2') {
+                Ok(())
+            } else {
+                let status_line = response.lines().next().unwrap_or("no response");
+                Err(io::Error::other(format!("Loki push failed: {status_line}")))
+            }
+        }
+    }
+
+    /// `host` with `:80` added unless it names a port; IPv6 literals must be bracketed,
+    /// as in `[::1]` or `[::1]:3100`.
+    pub(crate) fn with_default_port(host: &str) -> io::Result<String> {
+        let has_port = match host.strip_prefix('[') {
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((_, "")) => false,
+                Some((_, rest)) if rest.starts_with(':') => true,
+                _ => {
+                    let message = format!("malformed IPv6 host {host:?}");
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+                }
+            },
+            None if host.matches(':').count() > 1 => {
+                let message = format!("IPv6 host {host:?} must be in brackets");
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+            None => host.contains(':'),
+        };
+        Ok(if has_port { host.to_owned() } else { format!("{host}:80") })
+    }
+
+    /// Accepts connections on `listener` and logs every received record through the
+    /// local `Logger`, one thread per connection. Blocks for as long as the listener does.
+    ///
+    /// Records from one connection are logged in the order they were sent and keep the
+    /// sender's timestamps; records from different connections may interleave.
+    pub fn serve(listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            thread::Builder::new()
+                .name("firo-remote".to_owned())
+                .spawn(move || {
+                    for line in BufReader::new(stream).lines() {
+                        let Ok(line) = line else {
+                            break;
+                        };
+                        if let Some(record) = decode(&line) {
+                            Logger::dispatch(record);
+                        }
+                    }
+                })?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn encode(record: &LogRecord) -> String {
+        let nanos = record
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let (module, file, line) = match record.callsite {
+            Some(callsite) => (callsite.module, callsite.display_file(), callsite.line.to_string()),
+            None => ("", "", String::new()),
+        };
+        let mut encoded = format!(
+            "{nanos}\t{}\t{}\t{}\t{line}\t{}",
+            record.level.as_str(),
+            escape(module),
+            escape(file),
+            escape(&record.message)
+        );
+        for (key, value) in Logger::record_fields(record) {
+            let key = escape(key).replace('=', "\\=");
+            encoded.push_str(&format!("\t{key}={}", escape(&value)));
+        }
+        encoded.push('\n');
+        encoded
+    }
+
+    pub(crate) fn decode(line: &str) -> Option<LogRecord> {
+        let mut parts = line.split('\t');
+        let nanos: u64 = parts.next()?.parse().ok()?;
+        let level = LogLevel::from_name(parts.next()?)?;
+        let module = unescape(parts.next()?);
+        let file = unescape(parts.next()?);
+        let line_number = parts.next()?;
+        let message = unescape(parts.next()?);
+
+        let mut record = LogRecord::new(level, message);
+        record.timestamp = UNIX_EPOCH + Duration::from_nanos(nanos);
+        for field in parts {
+            let Some((key, value)) = split_field(field) else {
+                continue;
+            };
+            match key.as_str() {
+                "event_id" => record.event_id = Some(value),
+                "tenant_id" => record.tenant_id = Some(value),
+                _ => record.metadata.push((key, value)),
+            }
+        }
+        if !module.is_empty() {
+            record.callsite = Some(Callsite {
+                module: intern(&module),
+                file: intern(&file),
+                line: line_number.parse().unwrap_or(0),
+            });
+        }
+        Some(record)
+    }
+
+    /// Splits an encoded `key=value` column at the first `=` not escaped in the key.
+    fn split_field(field: &str) -> Option<(String, String)> {
+        let mut escaped = false;
+        for (index, c) in field.char_indices() {
+            match c {
+                '\\' if !escaped => escaped = true,
+                '=' if !escaped => {
+                    return Some((unescape(&field[..index]), unescape(&field[index + 1..])));
+                }
+                _ => escaped = false,
+            }
+        }
+        None
+    }
+
+    fn escape(text: &str) -> String {
+        text.replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+    }
+
+    fn unescape(text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                result.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('t') => result.push('\t'),
+                Some('n') => result.push('\n'),
+                Some(other) => result.push(other),
+                None => result.push('\\'),
+            }
+        }
+        result
+    }
+
+    /// Callsites need `&'static str`; each distinct module or file name is leaked once.
+    fn intern(text: &str) -> &'static str {
+        static INTERNED: Mutex<Option<HashSet<&'static str>>> = Mutex::new(None);
+        let mut interned = INTERNED.lock().unwrap();
+        let interned = interned.get_or_insert_with(HashSet::new);
+        if let Some(existing) = interned.get(text) {
+            return existing;
+        }
+        let leaked: &'static str = Box::leak(text.to_owned().into_boxed_str());
+        interned.insert(leaked);
+        leaked
+    }
+}
+
+/// Sends records to a syslog daemon: the local `/dev/log` socket, UDP or TCP.
+#[cfg(feature = "syslog")]
+pub mod syslog {
+    use crate::logger::{format_utc, syslog_hostname, LogRecord, Logger, SyslogFacility};
+    use std::io::{self, Write};
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+    #[cfg(unix)]
+    use std::os::unix::net::UnixDatagram;
+    #[cfg(unix)]
+    use std::path::Path;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// The message layout sent to the daemon.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum SyslogProtocol {
+        /// BSD syslog: `<14>Oct  1 10:32:45 host app[4242]: message key=value`, with the
+        /// time in UTC.
+        Rfc3164,
+        /// `<14>1 2024-10-01T10:32:45.123456Z host app 4242 - [firo@32473 key="value"] message`,
+        /// as `OutputFormat::Syslog` writes it.
+        Rfc5424,
+    }
+
+    #[derive(Debug)]
+    enum Transport {
+        #[cfg(unix)]
+        Unix(UnixDatagram),
+        Udp(UdpSocket),
+        /// Framed by octet counting (RFC 6587), so messages may contain newlines.
+        Tcp(Mutex<TcpStream>),
+    }
+
+    /// Sends every record as one syslog message, e.g.
+    /// `Logger::set_syslog_writer(SyslogWriter::udp("logs:514")?.facility(Local0))`.
+    /// Defaults to the `User` facility and the program name as the app name.
+    #[derive(Debug)]
+    pub struct SyslogWriter {
+        transport: Transport,
+        facility: SyslogFacility,
+        app_name: String,
+        protocol: SyslogProtocol,
+    }
+
+    impl SyslogWriter {
+        fn new(transport: Transport, protocol: SyslogProtocol) -> Self {
+            let app_name = Logger::script_name();
+            SyslogWriter {
+                transport,
+                facility: SyslogFacility::User,
+                app_name: if app_name.is_empty() { "-".to_owned() } else { app_name },
+                protocol,
+            }
+        }
+
+        /// The local daemon's `/dev/log` socket, in the RFC 3164 layout it expects.
+        #[cfg(unix)]
+        pub fn local() -> io::Result<Self> {
+            Ok(Self::unix("/dev/log")?.protocol(SyslogProtocol::Rfc3164))
+        }
+
+        /// A Unix datagram socket at `path`.
+        #[cfg(unix)]
+        pub fn unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+            let socket = UnixDatagram::unbound()?;
+            socket.connect(path)?;
+            Ok(Self::new(Transport::Unix(socket), SyslogProtocol::Rfc5424))
+        }
+
+        /// UDP datagrams to `addr`, usually port 514, over IPv4 or IPv6 as `addr` resolves.
+        pub fn udp<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+            let addr = first_addr(addr)?;
+            let socket = match addr {
+                SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+                SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+            };
+            socket.connect(addr)?;
+            Ok(Self::new(Transport::Udp(socket), SyslogProtocol::Rfc5424))
+        }
+
+        /// A TCP connection to `addr`, usually port 514 or 601. Connecting and each write
+        /// time out after 5 seconds, as with `TcpWriter`.
+        pub fn tcp<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+            Self::tcp_timeout(addr, Duration::from_secs(5))
+        }
+
+        /// Like `tcp`, with `timeout` for connecting and for each write. A write that
+        /// times out is returned as an error, like any failed send.
+        pub fn tcp_timeout<A: ToSocketAddrs>(addr: A, timeout: Duration) -> io::Result<Self> {
+            let stream = TcpStream::connect_timeout(&first_addr(addr)?, timeout)?;
+            stream.set_write_timeout(Some(timeout))?;
+            Ok(Self::new(Transport::Tcp(Mutex::new(stream)), SyslogProtocol::Rfc5424))
+        }
+
+        pub fn facility(mut self, facility: SyslogFacility) -> Self {
+            self.facility = facility;
+            self
+        }
+
+        /// The app name daemons file messages under; spaces become `_`.
+        pub fn app_name(mut self, name: &str) -> Self {
+            let name: String = name
+                .chars()
+                .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+                .take(48)
+                .collect();
+            self.app_name = if name.is_empty() { "-".to_owned() } else { name };
+            self
+        }
+
+        pub fn protocol(mut self, protocol: SyslogProtocol) -> Self {
+            self.protocol = protocol;
+            self
+        }
+
+        pub fn send(&self, record: &LogRecord) -> io::Result<()> {
+            let message = self.message(record);
+            match &self.transport {
+                #[cfg(unix)]
+                Transport::Unix(socket) => socket.send(message.as_bytes()).map(drop),
+                Transport::Udp(socket) => socket.send(message.as_bytes()).map(drop),
+                Transport::Tcp(stream) => {
+                    let framed = format!("{} {message}", message.len());
+                    stream.lock().unwrap().write_all(framed.as_bytes())
+                }
+            }
+        }
+
+        pub fn flush(&self) -> io::Result<()> {
+            match &self.transport {
+                Transport::Tcp(stream) => stream.lock().unwrap().flush(),
+                _ => Ok(()),
+            }
+        }
+
+        pub(crate) fn message(&self, record: &LogRecord) -> String {
+            match self.protocol {
+                SyslogProtocol::Rfc5424 => {
+                    Logger::format_syslog_as(record, self.facility, &self.app_name)
+                }
+                SyslogProtocol::Rfc3164 => {
+                    const MONTHS: [&str; 12] = [
+                        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct",
+                        "Nov", "Dec",
+                    ];
+                    let severity = record.level.syslog_severity();
+                    let priority = u16::from(self.facility.code()) * 8 + u16::from(severity);
+                    // `YYYY-MM-DD HH:MM:SS`
+                    let utc = format_utc(record.timestamp);
+                    let month: usize = utc[5..7].parse().unwrap_or(1);
+                    let day: u8 = utc[8..10].parse().unwrap_or(1);
+                    format!(
+                        "<{priority}>{} {day:>2} {} {} {}[{}]: {}{}",
+                        MONTHS[month.clamp(1, 12) - 1],
+                        &utc[11..],
+                        syslog_hostname(),
+                        self.app_name,
+                        std::process::id(),
+                        record.message,
+                        Logger::format_fields(record)
+                    )
+                }
+            }
+        }
+    }
+
+    fn first_addr<A: ToSocketAddrs>(addr: A) -> io::Result<SocketAddr> {
+        addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "syslog address has no socket address")
+        })
+    }
+}
+
+/// Helpers for common logging chores.
+pub mod utils {
+    use crate::logger::{LogLevel, LogRecord, Logger};
+    use std::io::{self, BufRead, BufReader, Read};
+    use std::path::Path;
+    use std::process::{Command, ExitStatus, Stdio};
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Runs `command`, logging stdout lines at `Info`, stderr lines at `Warning` and the exit status.
+    pub fn log_command(command: &mut Command) -> io::Result<ExitStatus> {
+        log_command_with_levels(command, LogLevel::Info, LogLevel::Warning)
+    }
+
+    /// Like `log_command`, with the levels for stdout and stderr lines chosen by the caller.
+    ///
+    /// Every record carries a `command=<program>` field. Output is logged from the calling
+    /// thread, so thread-scoped settings such as the tenant and capture apply to it.
+    pub fn log_command_with_levels(
+        command: &mut Command,
+        stdout_level: LogLevel,
+        stderr_level: LogLevel,
+    ) -> io::Result<ExitStatus> {
+        let program = command.get_program();
+        let name = Path::new(program)
+            .file_name()
+            .unwrap_or(program)
+            .to_string_lossy()
+            .into_owned();
+
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let (sender, lines) = mpsc::channel();
+        let mut readers = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            readers.push(spawn_reader(stdout, stdout_level, sender.clone()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            readers.push(spawn_reader(stderr, stderr_level, sender));
+        } else {
+            drop(sender);
+        }
+
+        for (level, line) in lines {
+            log_line(level, &name, line);
+        }
+        for reader in readers {
+            let _ = reader.join();
+        }
+
+        let status = child.wait()?;
+        if status.success() {
+            log_line(LogLevel::Success, &name, format!("{name} exited with {status}"));
+        } else {
+            log_line(LogLevel::Error, &name, format!("{name} exited with {status}"));
+        }
+        Ok(status)
+    }
+
+    fn spawn_reader<R: Read + Send + 'static>(
+        source: R,
+        level: LogLevel,
+        sender: mpsc::Sender<(LogLevel, String)>,
+    ) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let mut reader = BufReader::new(source);
+            let mut buffer = Vec::new();
+            while let Ok(read) = reader.read_until(b'\n', &mut buffer) {
+                if read == 0 {
+                    break;
+                }
+                let line = String::from_utf8_lossy(&buffer);
+                let line = line.trim_end_matches(['\n', '\r']).to_owned();
+                if sender.send((level, line)).is_err() {
+                    break;
+                }
+                buffer.clear();
+            }
+        })
+    }
+
+    fn log_line(level: LogLevel, command: &str, message: String) {
+        if !Logger::level_enabled(level, None) {
+            return;
+        }
+        let mut record = LogRecord::new(level, message);
+        record
+            .metadata
+            .push(("command".to_owned(), command.to_owned()));
+        Logger::dispatch(record);
+    }
+}
+
+/// Entry points into individual pipeline stages, for criterion benches in consumer crates.
+/// Not a stable API.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench {
+    use crate::logger::{LogRecord, Logger};
+    use std::fmt::Arguments;
+
+    /// Renders the message arguments, without a length cap.
+    pub fn render_message(message: Arguments) -> String {
+        Logger::render_message_limited(message, 0)
+    }
+
+    /// The file/text layout, without colours.
+    pub fn format_text(record: &LogRecord) -> String {
+        Logger::format_text(record, false)
+    }
+
+    /// The pretty console layout, without colours.
+    pub fn format_pretty(record: &LogRecord) -> String {
+        Logger::format_pretty(record, false)
+    }
+
+    /// Runs the registered filters only.
+    pub fn passes_filters(record: &LogRecord) -> bool {
+        Logger::passes_filters(record)
+    }
+
+    /// The whole pipeline after the record is built: filters, enrichment and every output.
+    pub fn dispatch(record: LogRecord) {
+        Logger::dispatch(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logger::*;
+    use std::sync::Mutex;
+
+    /// Serialises tests that change process-wide logger settings.
+    static GLOBAL_SETTINGS: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_format_message_error() {
+        let (console, file) = Logger::format_message(LogLevel::Error, "This is an error message");
+        assert!(console.contains("[ERROR]"));
+        assert!(file.contains("[ERROR]"));
+    }
+
+    #[test]
+    fn test_format_message_info() {
+        let (console, file) = Logger::format_message(LogLevel::Info, "Information log");
+        assert!(console.contains("[INFO]"));
+        assert!(file.contains("[INFO]"));
+    }
+
+    #[test]
+    fn test_format_message_debug() {
+        let (console, file) = Logger::format_message(LogLevel::Debug, "Debugging message");
+        assert!(console.contains("[DEBUG]"));
+        assert!(file.contains("[DEBUG]"));
+    }
+
+    #[test]
+    fn test_log_success() {
+        let (console, _) = Logger::format_message(LogLevel::Success, "Successful operation");
+        assert!(console.contains(Colours::GREEN)); // Ensure it uses the correct color
+        assert!(console.contains("[SUCCESS]"));
+    }
+    
+    #[test]
+    fn test_log_warning() {
+        let (console, _) = Logger::format_message(LogLevel::Warning, "Warning message");
+        assert!(console.contains(Colours::YELLOW)); // Ensure correct color is applied
+        assert!(console.contains("[WARNING]"));
+    }
+
+    #[test]
+    fn test_filters_drop_matching_records() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        Logger::add_filter(|record| !record.message.contains("noisy-filter-test"));
+        let noisy = LogRecord::new(LogLevel::Info, "noisy-filter-test payload");
+        let quiet = LogRecord::new(LogLevel::Info, "regular payload");
+        let (noisy_passes, quiet_passes) =
+            (Logger::passes_filters(&noisy), Logger::passes_filters(&quiet));
+        Logger::clear_filters();
+
+        assert!(!noisy_passes);
+        assert!(quiet_passes);
+    }
+
+    #[test]
+    fn test_firo_log_trait_injection() {
+        use std::cell::RefCell;
+
+        #[derive(Default)]
+        struct MockLogger {
+            records: RefCell<Vec<(LogLevel, String)>>,
+            flushes: RefCell<usize>,
+        }
+
+        impl FiroLog for MockLogger {
+            fn log_level(&self, level: LogLevel, args: std::fmt::Arguments) {
+                self.records.borrow_mut().push((level, args.to_string()));
+            }
+
+            fn flush(&self) {
+                *self.flushes.borrow_mut() += 1;
+            }
+        }
+
+        fn do_work(logger: &dyn FiroLog) -> u64 {
+            logger.warning(format_args!("disk at {}%", 91));
+            logger.flush();
+            logger.stats().records
+        }
+
+        let mock = MockLogger::default();
+        assert_eq!(do_work(&mock), 0);
+        assert_eq!(do_work(&NullLogger), 0);
+        assert_eq!(
+            *mock.records.borrow(),
+            vec![(LogLevel::Warning, "disk at 91%".to_owned())]
+        );
+        assert_eq!(*mock.flushes.borrow(), 1);
+    }
+
+    #[test]
+    fn test_injected_clock() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let start = UNIX_EPOCH + Duration::from_secs(1_727_778_765);
+        Logger::set_clock(SteppingClock::new(start, Duration::from_secs(1)));
+        let first = LogRecord::new(LogLevel::Info, "first");
+        let second = LogRecord::new(LogLevel::Info, "second");
+        Logger::reset_clock();
+
+        assert_eq!(first.timestamp, start);
+        assert_eq!(second.timestamp, start + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_format_utc() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01 00:00:00");
+        assert_eq!(
+            format_utc(UNIX_EPOCH + Duration::from_secs(1_727_778_765)),
+            "2024-10-01 10:32:45"
+        );
+        assert_eq!(
+            format_utc(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29 00:00:00"
+        );
+    }
+
+    #[test]
+    fn test_mode_output_is_stable() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        Logger::enable_test_mode();
+        crate::log_info!("user {} logged in", "alice");
+        crate::log_error!("boom");
+        let captured = Logger::disable_test_mode();
+        assert_eq!(
+            captured,
+            vec![
+                "2000-01-01 00:00:00 [INFO]: user alice logged in ".to_owned(),
+                "2000-01-01 00:00:00 [ERROR]: boom ".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mode_restores_settings() {
+        use std::time::{Duration, UNIX_EPOCH};
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        Logger::set_utc_timestamps(true);
+        Logger::set_colours(false);
+        Logger::enable_test_mode();
+        Logger::enable_test_mode();
+        let mut record = LogRecord::new(LogLevel::Info, "paid");
+        record.callsite = Some(Callsite {
+            module: "shop::billing",
+            file: "src/billing.rs",
+            line: 12,
+        });
+        record.metadata.push(("order".to_owned(), "42".to_owned()));
+        record.metadata.push(("amount".to_owned(), "9.99".to_owned()));
+        let json = Logger::format_as(
+            &record,
+            OutputFormat::Json { pretty: false },
+            false,
+            FieldStyle::default(),
+        );
+        Logger::disable_test_mode();
+        let restored = Logger::settings();
+        Logger::set_utc_timestamps(false);
+        Logger::set_colours(cfg!(feature = "colours"));
+
+        assert_eq!(
+            json,
+            "{\"timestamp\":\"2000-01-01 00:00:00\",\"level\":\"INFO\",\"severity_number\":9,\
+             \"module\":\"shop::billing\",\"file\":\"src/billing.rs\",\"line\":12,\
+             \"message\":\"paid\",\"fields\":{\"order\":\"42\",\"amount\":\"9.99\"}}"
+        );
+        assert_eq!(restored.get("utc_timestamps"), Some("true"));
+        assert_eq!(restored.get("colours"), Some("false"));
+        let after = LogRecord::new(LogLevel::Info, "after").timestamp;
+        assert!(after > UNIX_EPOCH + Duration::from_secs(946_684_800));
+    }
+
+    #[test]
+    fn test_capture_is_scoped_to_thread() {
+        let (value, captured) = Logger::capture(|| {
+            crate::log_warning!("inside capture");
+            std::thread::spawn(|| {
+                Logger::capture(|| crate::log_warning!("other thread"))
+            })
+            .join()
+            .unwrap();
+            42
+        });
+        assert_eq!(value, 42);
+        assert_eq!(captured.len(), 1);
+        assert!(captured[0].ends_with("[WARNING]: inside capture "));
+    }
+
+    #[test]
+    fn test_per_thread_log_file_name() {
+        let shared = Logger::log_file_name(false, None);
+        let named = std::thread::Builder::new()
+            .name("worker-1".to_owned())
+            .spawn(|| Logger::log_file_name(true, None))
+            .unwrap()
+            .join()
+            .unwrap();
+        let stem = shared.trim_end_matches(".log");
+        assert_eq!(named, format!("{stem}.worker-1.log"));
+    }
+
+    #[test]
+    fn test_route_matching() {
+        let audit = Route::to_file("audit.log").module("myapp::audit::*");
+        let errors = Route::to_file("errors.log").levels(&[LogLevel::Error]);
+
+        let mut record = LogRecord::new(LogLevel::Info, "login");
+        record.callsite = Some(Callsite {
+            module: "myapp::audit::session",
+            file: "src/audit/session.rs",
+            line: 10,
+        });
+        assert!(audit.matches(&record));
+        assert!(!errors.matches(&record));
+
+        record.callsite = Some(Callsite {
+            module: "myapp::http",
+            file: "src/http.rs",
+            line: 20,
+        });
+        record.level = LogLevel::Error;
+        assert!(!audit.matches(&record));
+        assert!(errors.matches(&record));
+    }
+
+    #[test]
+    fn test_route_by_field() {
+        let audit = Route::to_file("audit.log").field("audit", "true");
+        let tagged = Route::to_file("tagged.log").has_field("request_id");
+
+        let mut record = LogRecord::new(LogLevel::Info, "password changed");
+        assert!(!audit.matches(&record));
+        record.metadata.push(("audit".to_owned(), "true".to_owned()));
+        assert!(audit.matches(&record));
+        assert!(!tagged.matches(&record));
+        record.metadata.push(("request_id".to_owned(), "r-17".to_owned()));
+        assert!(tagged.matches(&record));
+        assert_eq!(record.field("request_id"), Some("r-17"));
+    }
+
+    #[test]
+    fn test_success_mapping() {
+        let success = LogRecord::new(LogLevel::Success, "deployed");
+        let info = LogRecord::new(LogLevel::Info, "deployed");
+        let mapping = SuccessMapping::default();
+        assert_eq!(Logger::interop_fields(&success, mapping), " outcome=success");
+        assert_eq!(Logger::interop_fields(&info, mapping), "");
+
+        let plain = SuccessMapping {
+            syslog_severity: 6,
+            outcome_field: false,
+        };
+        assert_eq!(Logger::interop_fields(&success, plain), "");
+        assert_eq!(LogLevel::Error.syslog_severity(), 3);
+    }
+
+    #[test]
+    fn test_key_sampled() {
+        let kept = (0..10_000)
+            .filter(|id| key_sampled(&format!("user-{id}"), 0.1))
+            .count();
+        assert!((800..1200).contains(&kept), "kept {kept}");
+        assert_eq!(key_sampled("user-42", 0.1), key_sampled("user-42", 0.1));
+        assert!(!key_sampled("user-42", 0.0));
+        assert!(key_sampled("user-42", 1.0));
+    }
+
+    #[test]
+    fn test_log_batch() {
+        let (_, captured) = Logger::capture(|| {
+            let mut batch = LogBatch::new();
+            batch.log(LogLevel::Info, format_args!("request step 1"));
+            crate::log_info!("unrelated");
+            batch.log(LogLevel::Info, format_args!("request step 2"));
+            assert_eq!(batch.len(), 2);
+            batch.commit();
+
+            let mut discarded = LogBatch::new();
+            discarded.log(LogLevel::Debug, format_args!("never written"));
+        });
+        assert_eq!(captured.len(), 3);
+        assert!(captured[0].ends_with("unrelated "));
+        assert!(captured[1].ends_with("request step 1 "));
+        assert!(captured[2].ends_with("request step 2 "));
+    }
+
+    #[test]
+    fn test_breadcrumbs_flush_on_error() {
+        let (_, captured) = Logger::capture(|| {
+            let _crumbs = Logger::breadcrumbs(2);
+            crate::log_debug!("crumb 1");
+            crate::log_debug!("crumb 2");
+            crate::log_debug!("crumb 3");
+            crate::log_info!("still quiet");
+            crate::log_error!("failed");
+        });
+        assert_eq!(captured.len(), 4);
+        assert!(captured[0].ends_with("still quiet "));
+        assert!(captured[1].ends_with("crumb 2 "));
+        assert!(captured[2].ends_with("crumb 3 "));
+        assert!(captured[3].ends_with("failed "));
+
+        let (_, captured) = Logger::capture(|| {
+            let _crumbs = Logger::breadcrumbs(10);
+            crate::log_debug!("discarded");
+        });
+        assert!(captured.is_empty());
+    }
+
+    #[test]
+    fn test_render_markup() {
+        let text = "deploy <b>done</b> in <green>3s</green>, a < b <x>";
+        assert_eq!(render_markup(text, false), "deploy done in 3s, a < b <x>");
+        assert_eq!(
+            render_markup(text, true),
+            "deploy \x1b[1mdone\x1b[0m in \x1b[32m3s\x1b[0m, a < b <x>"
+        );
+        assert!(matches!(render_markup("plain", true), std::borrow::Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_columns_align() {
+        let widths = ColumnWidths {
+            level: 7,
+            module: 10,
+        };
+        let mut short = LogRecord::new(LogLevel::Info, "one");
+        short.callsite = Some(Callsite {
+            module: "app::db",
+            file: "src/db.rs",
+            line: 1,
+        });
+        let mut long = LogRecord::new(LogLevel::Warning, "two");
+        long.callsite = Some(Callsite {
+            module: "app::http::handlers",
+            file: "src/http/handlers.rs",
+            line: 2,
+        });
+        let short = Logger::format_columns(&short, false, widths);
+        let long = Logger::format_columns(&long, false, widths);
+        let column = |line: &str, word| line[..line.find(word).unwrap()].chars().count();
+        assert_eq!(column(&short, "one"), column(&long, "two"));
+        assert!(long.contains(" WARNING …:handlers two"));
+        assert_eq!(fit_column("abc", 5), "abc");
+    }
+
+    #[test]
+    fn test_delimited_lines() {
+        let mut record = LogRecord::new(LogLevel::Warning, "disk \"almost\" full, 91%");
+        record.metadata.push(("host".to_owned(), "db-1".to_owned()));
+        let columns = [
+            CsvColumn::Level,
+            CsvColumn::Message,
+            CsvColumn::Field("host".to_owned()),
+            CsvColumn::Field("missing".to_owned()),
+        ];
+        assert_eq!(
+            Logger::format_delimited(&record, &columns, ','),
+            "WARNING,\"disk \"\"almost\"\" full, 91%\",db-1,"
+        );
+        record.message = "tab\there".to_owned();
+        assert_eq!(
+            Logger::format_delimited(&record, &columns, '\t'),
+            "WARNING\ttab\\there\tdb-1\t"
+        );
+        assert_eq!(
+            Logger::delimited_header(&columns, ','),
+            "level,message,host,missing"
+        );
+    }
+
+    #[test]
+    fn test_html_report() {
+        let mut error = LogRecord::new(LogLevel::Error, "payment <failed> & retried");
+        error.metadata.push(("order".to_owned(), "A-1".to_owned()));
+        let info = LogRecord::new(LogLevel::Info, "started");
+        let page = crate::report::html("Nightly <run>", &[info, error]);
+
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<title>Nightly &lt;run&gt;</title>"));
+        assert!(page.contains("payment &lt;failed&gt; &amp; retried"));
+        assert!(page.contains("<details><summary>1 fields</summary>order=A-1<br></details>"));
+        assert_eq!(page.matches("<tr data-level=").count(), 2);
+    }
+
+    #[test]
+    fn test_session_files() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let start = UNIX_EPOCH + Duration::from_secs(1_735_734_896);
+        assert_eq!(session_suffix(start, 4242), "-20250101-123456-4242");
+
+        let dir = std::env::temp_dir().join(format!("firo-sessions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "app-20250101-000000-1.log",
+            "app-20250102-000000-2.log",
+            "app-20250102-000000-2.worker.log",
+            "app-20250103-000000-3.log",
+            "app.log",
+            "app-notes.log",
+        ] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        assert_eq!(prune_sessions(&dir, "app", 2).unwrap(), 1);
+        let mut left: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            left,
+            [
+                "app-20250102-000000-2.log",
+                "app-20250102-000000-2.worker.log",
+                "app-20250103-000000-3.log",
+                "app-notes.log",
+                "app.log"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_default_log_dir() {
+        use std::path::PathBuf;
+
+        let env = |name: &str| match name {
+            "HOME" => Some(PathBuf::from("/home/ann")),
+            "LOCALAPPDATA" => Some(PathBuf::from("C:/Users/ann/AppData/Local")),
+            _ => None,
+        };
+        assert_eq!(
+            log_dir_for("linux", "app", env).unwrap(),
+            PathBuf::from("/home/ann/.local/state/app")
+        );
+        assert_eq!(
+            log_dir_for("macos", "app", env).unwrap(),
+            PathBuf::from("/home/ann/Library/Logs/app")
+        );
+        assert_eq!(
+            log_dir_for("windows", "app", env).unwrap(),
+            PathBuf::from("C:/Users/ann/AppData/Local/app/logs")
+        );
+        let xdg = |name: &str| (name == "XDG_STATE_HOME").then(|| PathBuf::from("/state"));
+        assert_eq!(log_dir_for("linux", "app", xdg).unwrap(), PathBuf::from("/state/app"));
+        assert_eq!(log_dir_for("linux", "app", |_| None), None);
+    }
+
+    #[test]
+    fn test_disk_budget() {
+        let dir = std::env::temp_dir().join(format!("firo-budget-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = [
+            ("app-20250101-000000-1.log", 400),
+            ("app.worker-1.log", 300),
+            ("app.log", 500),
+            ("other.log", 900),
+            ("app.log.important-notes", 900),
+        ];
+        for (name, len) in files {
+            std::fs::write(dir.join(name), vec![b'x'; len]).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let freed = enforce_disk_budget(&dir, "app", 700, &dir.join("app.log")).unwrap();
+        let exists = |name: &str| dir.join(name).exists();
+        let left = [
+            exists("app-20250101-000000-1.log"),
+            exists("app.worker-1.log"),
+            exists("app.log"),
+        ];
+        let other = exists("other.log") && exists("app.log.important-notes");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(freed, 700);
+        assert_eq!(left, [false, false, true]);
+        assert!(other);
+    }
+
+    #[test]
+    fn test_owned_log_file() {
+        for name in [
+            "app.log",
+            "app-20250101-120000-42.log",
+            "app.tenant_a.log",
+            "app-20250101-120000-42.acme.worker-1.log",
+        ] {
+            assert!(owned_log_file(name, "app"), "{name}");
+        }
+        for name in [
+            "app.log.important-notes",
+            "app.log.1.gz",
+            "application.log",
+            "app-notes.log",
+            "app-2025-01-01.log",
+            "app.a.b.c.log",
+            "app.my notes.log",
+        ] {
+            assert!(!owned_log_file(name, "app"), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_critical_level() {
+        assert!(LevelFilter::Min(LogLevel::Error).allows(LogLevel::Critical));
+        assert_eq!(LogLevel::Critical.syslog_severity(), 2);
+        assert_eq!(LogLevel::from_name("CRITICAL"), Some(LogLevel::Critical));
+        let record = LogRecord::new(LogLevel::Critical, "disk full");
+        let line = Logger::format_columns(&record, false, ColumnWidths::default());
+        assert!(line.contains(" CRITICAL ") && line.ends_with("disk full"));
+    }
+
+    #[test]
+    fn test_quiet_period_reverts() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        Logger::start_capture();
+        Logger::suppress_below(LogLevel::Debug, std::time::Duration::from_millis(20)).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        let captured = Logger::stop_capture();
+
+        assert_eq!(Logger::settings().get("quiet_period"), Some("None"));
+        let started = captured.iter().position(|line| line.contains("quiet period started"));
+        let ended = captured.iter().position(|line| line.contains("quiet period ended"));
+        assert!(started.unwrap() < ended.unwrap());
+    }
+
+    #[test]
+    fn test_warn_deprecated_once() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let ((), lines) = Logger::capture(|| {
+            for _ in 0..3 {
+                crate::warn_deprecated!(feature = "test::old_api", since = "1.2", note = "use new");
+            }
+            crate::warn_deprecated!(feature = "test::older_api", since = "0.9");
+        });
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("test::old_api is deprecated since 1.2: use new"));
+        assert!(lines[0].contains("deprecated.feature=test::old_api deprecated.since=1.2"));
+        assert!(lines[1].contains("deprecated.feature=test::older_api deprecated.since=0.9"));
+        assert!(!lines[1].contains("deprecated.note"));
+    }
+
+    #[test]
+    fn test_custom_level() {
+        let audit = Logger::register_level("TEST_AUDIT", 3, Colours::MAGENTA);
+        assert_eq!(Logger::register_level("TEST_AUDIT", 3, Colours::MAGENTA), audit);
+        assert_eq!(LogLevel::from_name("TEST_AUDIT"), Some(audit));
+        assert_eq!(audit.syslog_severity(), 4);
+        assert!(LevelFilter::Min(LogLevel::Warning).allows(audit));
+        assert!(!LevelFilter::Min(LogLevel::Error).allows(audit));
+
+        let record = LogRecord::new(audit, "alice signed in");
+        let line = Logger::format_columns(&record, true, ColumnWidths::default());
+        assert!(line.contains("\x1b[35mTEST_AUDIT\x1b[0m"));
+        assert!(Logger::format_text(&record, false).contains("[TEST_AUDIT]"));
+
+        let unregistered = LogLevel::Custom(u16::MAX);
+        assert_eq!(unregistered.as_str(), "CUSTOM");
+        assert_eq!(unregistered.severity(), LogLevel::Info.severity());
+        let record = LogRecord::new(unregistered, "no such level");
+        assert!(Logger::format_text(&record, false).contains("[CUSTOM]: no such level"));
+    }
+
+    #[test]
+    fn test_expire_records() {
+        use std::time::{Duration, UNIX_EPOCH};
+        assert_eq!(parse_retention("30d"), Some(Duration::from_secs(30 * 86_400)));
+        assert_eq!(parse_retention("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_retention("30 days"), None);
+        assert_eq!(parse_retention("36501d"), None);
+        assert_eq!(parse_retention("200000000000000d"), None);
+
+        let path = std::env::temp_dir().join(format!("firo-expire-{}.log", std::process::id()));
+        std::fs::write(
+            &path,
+            "2025-01-01 00:00:00 [INFO]: kept\n\
+             2025-01-01 00:00:00 [INFO]: card seen retention=1d expires=1000\n\
+             2025-01-01 00:00:00 [INFO]: audit retention=30d expires=3000 user=bob\n\
+             {\"level\":\"INFO\",\"message\":\"json card\",\"fields\":{\"expires\":\"1500\"}}\n\
+             {\"level\":\"INFO\",\"message\":\"json audit\",\"fields\":{\"expires\":\"2500\"}}\n",
+        )
+        .unwrap();
+        let now = UNIX_EPOCH + Duration::from_secs(2000);
+        let summary = crate::privacy::expire_records(&path, now).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((summary.records_scanned, summary.records_removed), (5, 2));
+        assert_eq!(summary.next_expiry, Some(UNIX_EPOCH + Duration::from_secs(2500)));
+        assert!(!contents.contains("card seen") && !contents.contains("json card"));
+        assert!(contents.contains("kept") && contents.contains("audit"));
+        assert!(contents.contains("json audit"));
+    }
+
+    #[test]
+    fn test_unbounded_retention_never_expires() {
+        use std::sync::Arc;
+
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reported);
+        Logger::set_error_handler(move |error| sink.lock().unwrap().push(error.to_string()));
+        let path = std::env::temp_dir().join(format!("firo-forever-{}.log", std::process::id()));
+        Logger::add_route(Route::to_file(&path).retention(std::time::Duration::MAX));
+        let ((), lines) = Logger::capture(|| {
+            crate::log_info!("kept forever");
+            crate::log_info!(retention = "200000000000000d", "bad hint");
+        });
+        Logger::clear_routes();
+        Logger::clear_error_handler();
+
+        assert!(lines.iter().all(|line| !line.contains("expires=")), "{lines:?}");
+        let reported = reported.lock().unwrap();
+        let bad_hint = "Invalid value \"200000000000000d\" for retention";
+        assert!(reported.iter().any(|error| error.starts_with(bad_hint)), "{reported:?}");
+    }
+
+    #[test]
+    fn test_syslog_format() {
+        use std::time::{Duration, UNIX_EPOCH};
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let mut record = LogRecord::new(LogLevel::Success, "deployed");
+        record.timestamp = UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+        record.metadata.push(("note".to_owned(), "a \"quoted\" ]".to_owned()));
+        let line = Logger::format_syslog(&record, SyslogFacility::Local0);
+
+        assert!(line.starts_with("<133>1 2023-11-14T22:13:20.123456Z "));
+        let pid = std::process::id();
+        assert!(line.contains(&format!(" {pid} - [firo@32473 note=\"a \\\"quoted\\\" \\]\"")));
+        assert!(line.ends_with(" outcome=\"success\"] deployed"));
+
+        let plain = LogRecord::new(LogLevel::Error, "boom");
+        let line = Logger::format_syslog(&plain, SyslogFacility::User);
+        assert!(line.starts_with("<11>1 ") && line.ends_with(" - - boom"));
+    }
+
+    /// A field value that must never be formatted.
+    struct Panics;
+
+    impl std::fmt::Display for Panics {
+        fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            panic!("formatted a filtered-out field");
+        }
+    }
+
+    #[test]
+    fn test_log_with_metadata_respects_levels() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        Logger::set_module_level("firo_logger::tests", LevelFilter::Min(LogLevel::Warning));
+        let ((), lines) = Logger::capture(|| {
+            let user = String::from("alice");
+            let expensive = Panics;
+            crate::log_with_metadata!(LogLevel::Info, { "user" => expensive }, "hidden");
+            crate::log_with_metadata!(
+                LogLevel::Error,
+                { "user" => user, "attempts" => 3 },
+                "login failed"
+            );
+            let mut record = LogRecord::new(LogLevel::Debug, "adapter debug");
+            record.callsite = Some(crate::callsite!());
+            Logger::log_record(record);
+            Logger::log_record(LogRecord::new(LogLevel::Info, "adapter info"));
+        });
+        Logger::clear_module_levels();
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("login failed user=alice attempts=3"));
+        assert!(lines[1].contains("adapter info"));
+    }
+
+    #[test]
+    fn test_emit_returns_write_errors() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let missing = std::env::temp_dir().join("firo-emit-missing-dir").join("routed.log");
+        Logger::add_route(Route::to_file(&missing).field("emit_test", "true"));
+        // Keep the main log file, which lives in the working directory, out of it.
+        Logger::set_file_level(LevelFilter::Off);
+        Logger::set_console_hook(|_| {});
+
+        let mut routed = LogRecord::new(LogLevel::Info, "routed");
+        routed.metadata.push(("emit_test".to_owned(), "true".to_owned()));
+        let result = Logger::emit(routed);
+        let plain = Logger::emit(LogRecord::new(LogLevel::Info, "plain"));
+        Logger::clear_routes();
+        Logger::clear_console_hook();
+        Logger::set_file_level(LevelFilter::Min(LogLevel::Debug));
+
+        assert!(matches!(result, Err(LoggerError::FileWrite { path, .. }) if path == missing));
+        assert!(plain.is_ok());
+    }
+
+    #[test]
+    fn test_rate_window() {
+        use std::time::{Duration, UNIX_EPOCH};
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let per = Duration::from_secs(10);
+        let mut window = RateWindow::new(at(100));
+        assert_eq!(window.admit(at(100), 2, per), Some(0));
+        assert_eq!(window.admit(at(101), 2, per), Some(0));
+        assert_eq!(window.admit(at(102), 2, per), None);
+        assert_eq!(window.admit(at(109), 2, per), None);
+        assert_eq!(window.admit(at(110), 2, per), Some(2));
+        assert_eq!(window.admit(at(111), 2, per), Some(0));
+        // A clock that jumps back starts a new window rather than locking the callsite out.
+        assert_eq!(window.admit(at(50), 2, per), Some(0));
+    }
+
+    #[test]
+    fn test_stats_json() {
+        use std::time::{Duration, UNIX_EPOCH};
+        let stats = LoggerStats {
+            taken_at: UNIX_EPOCH + Duration::from_millis(5_000),
+            records: 3,
+            errors: 1,
+            warnings: 0,
+            last_error_at: Some(UNIX_EPOCH + Duration::from_millis(4_000)),
+            clock_corrections: 0,
+            memory_in_use: 640,
+            memory_dropped: 2,
+            by_level: vec![(LogLevel::Info, 2), (LogLevel::Error, 1)],
+        };
+        assert_eq!(
+            stats.to_json(),
+            "{\"taken_at_ms\":5000,\"records\":3,\"errors\":1,\"warnings\":0,\
+             \"last_error_at_ms\":4000,\"clock_corrections\":0,\"memory_in_use\":640,\
+             \"memory_dropped\":2,\"by_level\":{\"INFO\":2,\"ERROR\":1}}"
+        );
+        assert!(!clock_stepped_back(0, 5_000));
+        assert!(!clock_stepped_back(5_000, 4_500));
+        assert!(clock_stepped_back(5_000, 1_000));
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let before = Logger::stats().records;
+        Logger::capture(|| Logger::log_record(LogRecord::new(LogLevel::Info, "counted")));
+        assert!(Logger::stats().records > before);
+    }
+
+    #[test]
+    fn test_pattern_layout() {
+        let pieces = parse_pattern("[%l] %m{target} %f:%L - %msg %kv 100%%").unwrap();
+        let mut record = LogRecord::new(LogLevel::Warning, "disk low");
+        record.callsite = Some(Callsite {
+            module: "app::disk",
+            file: "src/disk.rs",
+            line: 42,
+        });
+        record.metadata.push(("free".to_owned(), "3%".to_owned()));
+        let line = Logger::format_pattern(&record, false, &pieces);
+        assert_eq!(line, "[WARNING] app::disk src/disk.rs:42 - disk low free=3% 100%");
+
+        assert!(parse_pattern("%x").is_err());
+        assert!(parse_pattern("%l{upper}").is_err());
+        assert!(parse_pattern("%d{%H:%M").is_err());
+        #[cfg(feature = "chrono")]
+        assert_eq!(
+            parse_pattern("%d{%H:%M}").unwrap(),
+            vec![PatternPiece::Timestamp(Some("%H:%M".to_owned()))]
+        );
+    }
+
+    #[test]
+    fn test_try_log_is_written() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        Logger::start_capture();
+        assert_eq!(crate::try_log!(LogLevel::Warning, "queued {}", 7), Ok(()));
+        let mut captured = Vec::new();
+        for _ in 0..100 {
+            captured.extend(Logger::take_captured());
+            if captured.iter().any(|line| line.contains("queued 7")) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        Logger::stop_capture();
+        assert!(captured.iter().any(|line| line.contains("queued 7")));
+    }
+
+    #[test]
+    fn test_json_layout() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let mut record = LogRecord::new(LogLevel::Info, "said \"hi\"");
+        record.callsite = Some(Callsite {
+            module: "app::greet",
+            file: "src/greet.rs",
+            line: 7,
+        });
+        record.metadata.push(("user".to_owned(), "alice".to_owned()));
+        let date = Logger::format_timestamp(record.timestamp);
+
+        assert_eq!(
+            Logger::format_json(&record, false),
+            format!(
+                "{{\"timestamp\":\"{date}\",\"level\":\"INFO\",\"severity_number\":9,\
+                 \"module\":\"app::greet\",\
+                 \"file\":\"src/greet.rs\",\"line\":7,\"message\":\"said \\\"hi\\\"\",\
+                 \"fields\":{{\"user\":\"alice\"}}}}"
+            )
+        );
+        let pretty = Logger::format_json(&LogRecord::new(LogLevel::Error, "boom"), true);
+        assert!(pretty.starts_with("{\n  \"timestamp\": \""));
+        assert!(pretty.ends_with(
+            ",\n  \"level\": \"ERROR\",\n  \"severity_number\": 17,\n  \"message\": \"boom\"\n}"
+        ));
+
+        Logger::set_json_severity_scale(SeverityScale::Syslog);
+        let syslog = Logger::format_json(&LogRecord::new(LogLevel::Warning, "low"), false);
+        Logger::set_json_severity_scale(SeverityScale::OpenTelemetry);
+        assert!(syslog.contains("\"level\":\"WARNING\",\"severity_number\":4,"));
+    }
+
+    #[test]
+    fn test_collect_batch() {
+        use std::time::{Duration, Instant};
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for n in 1..=5 {
+            sender.send(n).unwrap();
+        }
+        assert_eq!(collect_batch(&receiver, 0, 3, Duration::from_secs(5)), vec![0, 1, 2]);
+        assert_eq!(collect_batch(&receiver, 0, 1, Duration::from_secs(5)), vec![0]);
+
+        let start = Instant::now();
+        let batch = collect_batch(&receiver, 0, 100, Duration::from_millis(20));
+        assert_eq!(batch, vec![0, 3, 4, 5]);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_logger_error_codes() {
+        use std::io::{Error, ErrorKind};
+        let full = LoggerError::FileWrite {
+            path: std::path::PathBuf::from("logs/app.log"),
+            source: Error::from(ErrorKind::StorageFull),
+        };
+        assert_eq!(full.code(), "file_disk_full");
+        let json = full.to_json();
+        assert!(json.starts_with("{\"code\":\"file_disk_full\",\"message\":\"Error writing"));
+        assert!(json.ends_with(",\"path\":\"logs/app.log\"}"));
+
+        let closed = LoggerError::ConsoleClosed(Error::from(ErrorKind::BrokenPipe));
+        assert_eq!(closed.code(), "console_closed");
+        assert_eq!(
+            LoggerError::Conflict("x").to_json(),
+            "{\"code\":\"conflict\",\"message\":\"Conflicting settings: x\"}"
+        );
+    }
+
+    #[test]
+    fn test_level_macro_fields() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let ((), lines) = Logger::capture(|| {
+            let path = "/users";
+            crate::log_warning!(user_id = 42, latency_ms = 12.5, "request finished");
+            crate::log_error!(path = path, "failed {} of {}", 1, 3);
+            crate::log_warning!("plain {path}");
+            crate::log_info!(user_id = 7, path = path);
+            crate::log_info!(user_id = 8,);
+        });
+        assert!(lines[0].contains("request finished user_id=42 latency_ms=12.5"));
+        assert!(lines[1].contains("failed 1 of 3 path=/users"));
+        assert!(lines[2].contains("plain /users"));
+        assert!(lines[3].ends_with("[INFO]:  user_id=7 path=/users "), "{}", lines[3]);
+        assert!(lines[4].ends_with("[INFO]:  user_id=8 "), "{}", lines[4]);
+    }
+
+    #[test]
+    fn test_field_style() {
+        let mut record = LogRecord::new(LogLevel::Info, "hello");
+        record.metadata = vec![("zone".into(), "eu".into()), ("app".into(), "api".into())];
+        let text = |placement, sorted| {
+            Logger::format_text_with(&record, false, FieldStyle { placement, sorted })
+        };
+
+        assert!(text(FieldPlacement::Layout, false).ends_with("hello zone=eu app=api "));
+        assert!(text(FieldPlacement::Inline, true).ends_with("hello app=api zone=eu "));
+        assert!(text(FieldPlacement::Omitted, true).ends_with("hello "));
+        let block = text(FieldPlacement::Block, true);
+        assert!(block.ends_with("hello \n    app  = api\n    zone = eu"));
+
+        let pretty = Logger::format_pretty_with(
+            &record,
+            false,
+            FieldStyle {
+                placement: FieldPlacement::Inline,
+                sorted: false,
+            },
+        );
+        assert!(pretty.ends_with("hello zone=eu app=api"));
+    }
+
+    #[cfg(feature = "syslog")]
+    #[test]
+    fn test_syslog_writer_over_udp() {
+        use crate::syslog::{SyslogProtocol, SyslogWriter};
+        use std::net::UdpSocket;
+
+        let daemon = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let writer = SyslogWriter::udp(daemon.local_addr().unwrap())
+            .unwrap()
+            .facility(SyslogFacility::Local0)
+            .app_name("api server");
+        let mut record = LogRecord::new(LogLevel::Warning, "disk almost full");
+        record.timestamp = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_727_778_765);
+        record.metadata = vec![("free".into(), "2%".into())];
+
+        writer.send(&record).unwrap();
+        let mut buffer = [0; 512];
+        let len = daemon.recv(&mut buffer).unwrap();
+        let received = std::str::from_utf8(&buffer[..len]).unwrap();
+        assert!(received.starts_with("<132>1 2024-10-01T10:32:45.000000Z "));
+        assert!(received.contains(" api_server "));
+        assert!(received.ends_with(r#"[firo@32473 free="2%"] disk almost full"#));
+
+        let writer = writer.protocol(SyslogProtocol::Rfc3164);
+        writer.send(&record).unwrap();
+        let len = daemon.recv(&mut buffer).unwrap();
+        let received = std::str::from_utf8(&buffer[..len]).unwrap();
+        assert!(received.starts_with("<132>Oct  1 10:32:45 "));
+        assert!(received.ends_with(&format!(
+            " api_server[{}]: disk almost full free=2%",
+            std::process::id()
+        )));
+
+        // IPv6 daemons work too, where the host has IPv6 at all.
+        if let Ok(daemon) = UdpSocket::bind("[::1]:0") {
+            let writer = SyslogWriter::udp(daemon.local_addr().unwrap()).unwrap();
+            writer.send(&record).unwrap();
+            assert!(daemon.recv(&mut buffer).unwrap() > 0);
+        }
+    }
+
+    #[test]
+    fn test_field_measures() {
+        assert_eq!(FieldUnit::Millis.humanize(850), "850ms");
+        assert_eq!(FieldUnit::Millis.humanize(1_234), "1.2s");
+        assert_eq!(FieldUnit::Millis.humanize(125_000), "2m05s");
+        assert_eq!(FieldUnit::Millis.humanize(12_000_000), "3h20m");
+        assert_eq!(FieldUnit::Bytes.humanize(512), "512 B");
+        assert_eq!(FieldUnit::Bytes.humanize(3_565_158), "3.4 MiB");
+
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let ((), lines) = Logger::capture(|| {
+            crate::log_info!(took = field_duration(1_234), size = field_bytes(2048), "saved");
+        });
+        assert!(lines[0].contains("saved took=1.2s size=2.0 KiB"));
+
+        let mut record = LogRecord::new(LogLevel::Info, "saved");
+        record.metadata = vec![("took".into(), "1234".into()), ("id".into(), "7".into())];
+        record.field_units = vec![("took".into(), FieldUnit::Millis)];
+        let json = Logger::format_json(&record, false);
+        assert!(json.ends_with(r#""fields":{"took":1234,"id":"7"}}"#));
+    }
+
+    #[test]
+    fn test_tcp_writer_reconnects() {
+        use crate::remote::TcpWriter;
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let writer = TcpWriter::new(addr.to_string())
+            .format(OutputFormat::Text)
+            .retry_interval(std::time::Duration::ZERO);
+        assert!(writer.send(&LogRecord::new(LogLevel::Info, "while down")).is_err());
+        assert_eq!(writer.queued(), 1);
+
+        let listener = TcpListener::bind(addr).unwrap();
+        writer.send(&LogRecord::new(LogLevel::Info, "back up")).unwrap();
+        assert_eq!(writer.queued(), 0);
+        let (stream, _) = listener.accept().unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        assert!(lines.next().unwrap().unwrap().contains("while down"));
+        assert!(lines.next().unwrap().unwrap().contains("back up"));
+
+        let full = TcpWriter::new("127.0.0.1:1").queue_capacity(2);
+        for _ in 0..3 {
+            let _ = full.send(&LogRecord::new(LogLevel::Info, "dropped"));
+        }
+        assert_eq!((full.queued(), full.dropped()), (2, 1));
+    }
+
+    #[test]
+    fn test_level_override() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        Logger::set_level(LevelFilter::Min(LogLevel::Warning));
+        let ((), lines) = Logger::capture(|| {
+            crate::log_debug!("hidden before");
+            Logger::with_level(LogLevel::Debug, || {
+                crate::log_debug!("shown inside");
+                let _info = Logger::override_level(LogLevel::Info);
+                crate::log_debug!("hidden by the inner override");
+                crate::log_info!("shown by the inner override");
+            });
+            crate::log_debug!("hidden after");
+        });
+        Logger::set_level(LevelFilter::Min(LogLevel::Debug));
+
+        assert_eq!(lines.len(), 2, "{lines:?}");
+        assert!(lines[0].contains("shown inside"));
+        assert!(lines[1].contains("shown by the inner override"));
+    }
+
+    #[test]
+    fn test_module_fields() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        Logger::add_module_fields("firo_logger::*", &[("domain", "logging")]);
+        Logger::add_module_fields("firo_logger::tests", &[("suite", "unit")]);
+        Logger::add_module_fields("payments::*", &[("domain", "payments")]);
+        let ((), lines) = Logger::capture(|| {
+            crate::log_info!("first");
+            crate::log_info!("second");
+            Logger::log_record(LogRecord::new(LogLevel::Info, "no callsite"));
+        });
+        Logger::clear_module_fields();
+
+        assert!(lines[0].contains("first domain=logging suite=unit"));
+        assert!(lines[1].contains("second domain=logging suite=unit"));
+        assert!(!lines[2].contains("domain="));
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        use crate::remote::TcpWriter;
+
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let writer = TcpWriter::new("127.0.0.1:1").format(OutputFormat::Text);
+        let _ = writer.send(&LogRecord::new(LogLevel::Info, "queued"));
+        let dropped = Logger::stats().memory_dropped;
+        Logger::set_memory_budget(Some(1));
+        let _ = writer.send(&LogRecord::new(LogLevel::Info, "over budget"));
+        Logger::set_memory_budget(None);
+        assert_eq!((writer.queued(), writer.dropped()), (1, 1));
+        assert_eq!(Logger::stats().memory_dropped, dropped + 1);
+
+        let in_use = Logger::stats().memory_in_use;
+        drop(writer);
+        assert!(Logger::stats().memory_in_use + "queued".len() < in_use);
+    }
+
+    #[test]
+    fn test_loki_writer() {
+        use crate::remote::{with_default_port, LokiWriter};
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            // The body ends with the closing `]}` of the streams array.
+            while !request.ends_with(b"]}]}") {
+                let len = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..len]);
+            }
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let writer = LokiWriter::new(&url)
+            .unwrap()
+            .format(OutputFormat::Text)
+            .label("job", "api")
+            .field_label("region")
+            .batch_size(3);
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let mut later = LogRecord::new(LogLevel::Info, "later");
+        later.timestamp = at(20);
+        let mut earlier = LogRecord::new(LogLevel::Info, "earlier");
+        earlier.timestamp = at(10);
+        let mut failed = LogRecord::new(LogLevel::Error, "failed");
+        failed.metadata.push(("region".into(), "eu".into()));
+        writer.send(&later).unwrap();
+        writer.send(&earlier).unwrap();
+        writer.send(&failed).unwrap();
+        writer.flush().unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /loki/api/v1/push HTTP/1.1\r\n"));
+        let body = request.split("\r\n\r\n").nth(1).unwrap();
+        assert!(body.contains(r#"{"stream":{"job":"api","level":"error","region":"eu"}"#));
+        let info = body.find(r#"{"stream":{"job":"api","level":"info"},"values":[["10000000000","#);
+        assert!(info.is_some(), "{body}");
+        assert!(body.find("earlier").unwrap() < body.find("later").unwrap());
+
+        let mut latest = std::collections::BTreeMap::from([("{}".to_owned(), 50)]);
+        let streams = std::collections::BTreeMap::from([("{}".to_owned(), vec![(40, "x".into())])]);
+        let payload = LokiWriter::payload(streams, &mut latest);
+        assert_eq!(payload, r#"{"streams":[{"stream":{},"values":[["50","x"]]}]}"#);
+
+        assert_eq!(with_default_port("loki").unwrap(), "loki:80");
+        assert_eq!(with_default_port("loki:3100").unwrap(), "loki:3100");
+        assert_eq!(with_default_port("[::1]").unwrap(), "[::1]:80");
+        assert_eq!(with_default_port("[::1]:3100").unwrap(), "[::1]:3100");
+        assert!(with_default_port("::1").is_err());
+        assert!(with_default_port("[::1").is_err());
+    }
+
+    #[test]
+    fn test_check_file_output() {
+        let dir = std::env::temp_dir().join(format!("firo-probe-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = Logger::probe_file(dir.join("app.log")).unwrap();
+        assert!(path.is_absolute() && path.starts_with(&dir));
+        assert!(!path.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        let error = Logger::probe_file(dir.join("app.log")).unwrap_err();
+        assert_eq!(error.code(), "file_unwritable");
+        let message = error.to_string();
+        assert!(message.contains(&format!("directory {} does not exist", dir.display())));
+        assert!(message.contains("nearest existing directory"));
+        assert!(error.to_json().contains("\"diagnostics\":"));
+    }
+
+    #[test]
+    fn test_file_fallback() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let missing = std::env::temp_dir().join("firo-fallback-missing").join("app.log");
+        let unwritable = || Logger::probe_file(missing.clone()).unwrap_err();
+        assert!(Logger::fall_back_to_console(unwritable()).is_err());
+
+        let lines = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let sink = std::sync::Arc::clone(&lines);
+        Logger::set_console_hook(move |line| sink.lock().unwrap().push(line.to_owned()));
+        Logger::set_file_fallback(true);
+        Logger::set_output(Output::File);
+        let result = Logger::fall_back_to_console(unwritable());
+        let output = Logger::settings().get("output").map(str::to_owned);
+        Logger::set_output(Output::ConsoleAndFile);
+        Logger::set_file_fallback(false);
+        Logger::clear_console_hook();
+
+        assert!(result.is_ok());
+        assert_eq!(output.as_deref(), Some("Console"));
+        let lines = lines.lock().unwrap();
+        assert!(lines.iter().any(|line| {
+            line.contains("[WARNING]")
+                && line.contains("Logging to the console only")
+                && line.contains("error_code=file_unwritable")
+        }));
+    }
+
+    #[test]
+    fn test_route_level_range() {
+        let chatter = Route::to_file("chatter.log")
+            .min_level(LogLevel::Debug)
+            .max_level(LogLevel::Info);
+        let level = |level| LogRecord::new(level, "message");
+        assert!(chatter.matches(&level(LogLevel::Debug)));
+        assert!(chatter.matches(&level(LogLevel::Success)));
+        assert!(!chatter.matches(&level(LogLevel::Warning)));
+        assert!(!chatter.matches(&level(LogLevel::Error)));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("audit::*", "audit::log"));
+        assert!(glob_match("*::db", "app::db"));
+        assert!(glob_match("a*b*c", "abc"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+        assert!(!glob_match("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn test_errors_are_counted() {
+        Logger::capture(|| crate::log_error!("build step failed"));
+        assert!(Logger::had_errors());
+        assert_eq!(Logger::exit_code_from_logs(), 1);
+    }
+
+    #[cfg(feature = "event-id")]
+    #[test]
+    fn test_event_ids_are_time_sortable() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let earlier = new_event_id(UNIX_EPOCH + Duration::from_secs(1_727_778_765));
+        let later = new_event_id(UNIX_EPOCH + Duration::from_secs(1_727_778_766));
+        assert_eq!(earlier.len(), 36);
+        assert_eq!(&earlier[14..15], "7");
+        assert!(earlier < later);
+
+        let mut record = LogRecord::new(LogLevel::Info, "hello");
+        record.event_id = Some(earlier.clone());
+        let file = Logger::format_text(&record, false);
+        assert!(file.ends_with(&format!("hello event_id={earlier} ")));
+    }
+
+    #[test]
+    fn test_sequence_numbers_increase() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        Logger::set_include_sequence(true);
+        let (_, captured) = Logger::capture(|| {
+            crate::log_info!("first");
+            crate::log_info!("second");
+        });
+        Logger::set_include_sequence(false);
+
+        let seq = |line: &str| -> u64 {
+            line.trim_end().rsplit("seq=").next().unwrap().parse().unwrap()
+        };
+        assert!(seq(&captured[1]) > seq(&captured[0]));
+    }
+
+    #[test]
+    fn test_log_event_uses_catalog() {
+        Logger::register_event(EventCode {
+            code: "E1042",
+            level: LogLevel::Warning,
+            template: "user {user} denied access to {path}",
+            docs_url: Some("https://example.com/E1042"),
+        });
+        let (_, captured) = Logger::capture(|| {
+            let user = "bob";
+            crate::log_event!(E1042, user = user, path = "/admin");
+            crate::log_event!(E9999, attempt = 3);
+        });
+        assert!(captured[0].contains(
+            "[WARNING]: [E1042] user bob denied access to /admin (see https://example.com/E1042)"
+        ));
+        assert!(captured[1].contains("[INFO]: [E9999] attempt=3"));
+    }
+
+    #[test]
+    fn test_pretty_format() {
+        let mut record = LogRecord::new(LogLevel::Warning, "cache miss");
+        record.callsite = Some(Callsite {
+            module: "app::cache",
+            file: "src/cache.rs",
+            line: 42,
+        });
+        record.sequence = Some(7);
+        let pretty = Logger::format_pretty(&record, false);
+        let lines: Vec<&str> = pretty.lines().collect();
+        assert!(lines[0].trim_start().starts_with('+'));
+        assert!(lines[0].ends_with("WARNING cache miss src/cache.rs:42"));
+        assert_eq!(lines[1].trim(), "seq = 7");
+    }
+
+    #[test]
+    fn test_epoch_timestamp_formats() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let timestamp = UNIX_EPOCH + Duration::new(1_727_778_765, 123_456_789);
+        let render = |format| Logger::format_timestamp_as(timestamp, format);
+        assert_eq!(render(TimestampFormat::UnixSeconds), "1727778765");
+        assert_eq!(render(TimestampFormat::UnixMillis), "1727778765123");
+        assert_eq!(render(TimestampFormat::UnixNanos), "1727778765123456789");
+        assert!(render(TimestampFormat::DateTimeNanos).ends_with(":45.123456789"));
+    }
+
+    #[test]
+    fn test_duration_histogram() {
+        use std::time::Duration;
+
+        for millis in 1..=100 {
+            Logger::record_duration("histogram-test", Duration::from_millis(millis));
+        }
+        let value = crate::time_block!("histogram-test-block", { 2 + 2 });
+        assert_eq!(value, 4);
+        assert_eq!(Logger::duration_summary("histogram-test-block").unwrap().count, 1);
+
+        let summary = Logger::duration_summary("histogram-test").unwrap();
+        assert_eq!(summary.count, 100);
+        assert_eq!(summary.max, Duration::from_millis(100));
+        // Buckets are powers of two, so each percentile is within 2x of the exact value.
+        assert!(summary.p50 >= Duration::from_millis(50) && summary.p50 <= Duration::from_millis(100));
+        assert!(summary.p99 >= Duration::from_millis(99));
+        assert!(summary.p50 <= summary.p95 && summary.p95 <= summary.p99);
+    }
+
+    #[test]
+    fn test_http_log() {
+        let (_, captured) = Logger::capture(|| {
+            crate::http_log!(
+                method = "GET",
+                path = "/users",
+                status = 200,
+                latency_ms = 12.5,
+                bytes = 512,
+                remote_ip = "10.0.0.1",
+            );
+            crate::http_log!(method = "POST", path = "/login", status = 503);
+        });
+        assert!(captured[0].contains(
+            "[INFO]: method=GET path=/users status=200 latency_ms=12.5 bytes=512 remote_ip=10.0.0.1"
+        ));
+        assert!(captured[1].contains("[ERROR]: method=POST path=/login status=503 latency_ms=0 bytes=0 "));
+    }
+
+    #[test]
+    fn test_enricher_refreshes_in_background() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let enricher = Enricher::spawn(Duration::from_millis(1), move || {
+            let version = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            vec![("config_version".to_owned(), version.to_string())]
+        });
+        assert_eq!(*enricher.fields(), [("config_version".to_owned(), "1".to_owned())]);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while enricher.fields()[0].1 == "1" && Instant::now() < deadline {
+            std::thread::yield_now();
+        }
+        assert_ne!(enricher.fields()[0].1, "1");
+    }
+
+    #[test]
+    fn test_tenant_scope() {
+        let (_, captured) = Logger::capture(|| {
+            let _outer = Logger::set_tenant("acme");
+            {
+                let _inner = Logger::set_tenant("globex");
+                crate::log_info!("inner");
+            }
+            crate::log_info!("outer");
+        });
+        assert!(captured[0].contains("inner tenant_id=globex"));
+        assert!(captured[1].contains("outer tenant_id=acme"));
+
+        let shared = Logger::log_file_name(false, None);
+        let stem = shared.trim_end_matches(".log");
+        assert_eq!(
+            Logger::log_file_name(false, Some("acme/eu")),
+            format!("{stem}.acme_eu.log")
+        );
+    }
+
+    #[test]
+    fn test_forget_subject() {
+        use crate::privacy::{forget_subject, ForgetAction};
+
+        let path = std::env::temp_dir().join(format!("firo-forget-{}.log", std::process::id()));
+        let contents = "2024-10-01 10:32:45 [INFO]: login user_id=42 \n\
+                        2024-10-01 10:32:46 [INFO]: login user_id=420 \n\
+                        2024-10-01 10:32:47 [INFO]: logout user_id=42 ip=1.2.3.4 \n";
+
+        std::fs::write(&path, contents).unwrap();
+        let summary = forget_subject(&path, "user_id", "42", ForgetAction::Redact).unwrap();
+        assert_eq!((summary.records_scanned, summary.records_matched), (3, 2));
+        let redacted = std::fs::read_to_string(&path).unwrap();
+        assert!(redacted.contains("login user_id=[REDACTED] \n"));
+        assert!(redacted.contains("login user_id=420 \n"));
+        assert!(redacted.contains("logout user_id=[REDACTED] ip=1.2.3.4 \n"));
+
+        std::fs::write(&path, contents).unwrap();
+        forget_subject(&path, "user_id", "42", ForgetAction::Remove).unwrap();
+        let remaining = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(remaining, "2024-10-01 10:32:46 [INFO]: login user_id=420 \n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_backend_bridge() {
+        use std::sync::{Arc, Mutex};
+
+        struct Collector(Arc<Mutex<Vec<(log::Level, String, String)>>>);
+
+        impl log::Log for Collector {
+            fn enabled(&self, _metadata: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                self.0.lock().unwrap().push((
+                    record.level(),
+                    record.target().to_owned(),
+                    record.args().to_string(),
+                ));
+            }
+
+            fn flush(&self) {}
+        }
+
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let forwarded = Arc::new(Mutex::new(Vec::new()));
+        Logger::set_log_backend(Box::new(Collector(Arc::clone(&forwarded))));
+        Logger::capture(|| crate::log_success!("bridge-test deployed"));
+        Logger::clear_log_backend();
+
+        let forwarded = forwarded.lock().unwrap();
+        let entry = forwarded
+            .iter()
+            .find(|(_, _, message)| message.starts_with("bridge-test"))
+            .unwrap();
+        assert_eq!(entry.0, log::Level::Info);
+        assert_eq!(entry.1, module_path!());
+        assert_eq!(entry.2, "bridge-test deployed outcome=success");
+    }
+
+    #[test]
+    fn test_remote_round_trip() {
+        use crate::remote::{decode, encode, serve, RemoteWriter};
+        use std::time::{Duration, Instant, UNIX_EPOCH};
+
+        let mut record = LogRecord::new(LogLevel::Warning, "tab\there\nnewline \\ slash");
+        record.timestamp = UNIX_EPOCH + Duration::new(1_727_778_765, 42);
+        record.tenant_id = Some("acme".to_owned());
+        record.metadata.push(("a=b".to_owned(), "x\ty=z".to_owned()));
+        record.callsite = Some(Callsite {
+            module: "app::worker",
+            file: "src/worker.rs",
+            line: 7,
+        });
+        let encoded = encode(&record);
+        assert_eq!(encoded.matches('\n').count(), 1);
+        let decoded = decode(encoded.trim_end_matches('\n')).unwrap();
+        assert_eq!(decoded.level, record.level);
+        assert_eq!(decoded.message, record.message);
+        assert_eq!(decoded.timestamp, record.timestamp);
+        assert_eq!(decoded.callsite, record.callsite);
+        assert_eq!(decoded.tenant_id, record.tenant_id);
+        assert_eq!(decoded.metadata, record.metadata);
+
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || serve(listener));
+        Logger::start_capture();
+        let writer = RemoteWriter::connect(addr).unwrap();
+        writer.send(&record).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.dropped(), 0);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut received = Vec::new();
+        while received.is_empty() && Instant::now() < deadline {
+            received = Logger::take_captured();
+            std::thread::yield_now();
+        }
+        Logger::stop_capture();
+        assert!(received[0].contains("[WARNING]: tab\there\nnewline \\ slash"));
+    }
+
+    #[test]
+    fn test_spawn_logged_inherits_tenant() {
+        let _tenant = Logger::set_tenant("acme");
+        let (name, captured) = Logger::spawn_logged("tenant-worker", || {
+            Logger::capture(|| {
+                crate::log_info!("from worker");
+                std::thread::current().name().map(str::to_owned)
+            })
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+        assert_eq!(name.as_deref(), Some("tenant-worker"));
+        assert!(captured[0].ends_with("from worker tenant_id=acme "));
+    }
+
+    #[test]
+    fn test_log_context_wrap() {
+        let context = {
+            let _tenant = Logger::set_tenant("pool");
+            LogContext::current()
+        };
+        let log_item = context.wrap(|item: u32| crate::log_info!("item {item}"));
+        let (_, captured) = std::thread::spawn(move || Logger::capture(|| (0..2).for_each(&log_item)))
+            .join()
+            .unwrap();
+        assert_eq!(captured.len(), 2);
+        assert!(captured[1].ends_with("item 1 tenant_id=pool "));
+
+        let (_, captured) = Logger::capture(|| crate::log_info!("outside"));
+        assert!(captured[0].ends_with("outside "));
+    }
+
+    #[test]
+    fn test_settings_diff() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let before = Logger::settings();
+        Logger::set_include_sequence(true);
+        let after = Logger::settings();
+        Logger::set_include_sequence(false);
+
+        assert_eq!(after.get("include_sequence"), Some("true"));
+        assert!(before
+            .diff(&after)
+            .contains(&"include_sequence: false -> true".to_owned()));
+        assert!(Logger::describe().contains("output"));
+    }
+
+    #[test]
+    fn test_env_config() {
+        let env = |name: &str| match name {
+            "FIRO_LOG_OUTPUT" => Some("Console".to_owned()),
+            "FIRO_LOG_COLOURS" => Some("0".to_owned()),
+            "FIRO_LOG_TIMESTAMP" => Some("unix-millis".to_owned()),
+            _ => None,
+        };
+        let (config, errors) = EnvConfig::read(env);
+        assert_eq!(
+            config,
+            EnvConfig {
+                output: Some(Output::Console),
+                colours: Some(false),
+                ..EnvConfig::default()
+            }
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .to_string()
+            .starts_with("Invalid value \"unix-millis\" for FIRO_LOG_TIMESTAMP"));
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_validate_datetime_pattern() {
+        assert!(validate_datetime_pattern("%d/%m/%Y %H:%M").is_ok());
+        let error = validate_datetime_pattern("%Y-%Q").unwrap_err();
+        assert!(matches!(error, LoggerError::Config { ref value, .. } if value == "%Y-%Q"));
+    }
+
+    #[test]
+    fn test_config_conflicts() {
+        assert!(config_conflicts(Output::ConsoleAndFile, true, true, true).is_empty());
+        assert_eq!(config_conflicts(Output::Console, true, true, true).len(), 2);
+        assert_eq!(
+            config_conflicts(Output::File, false, false, true),
+            ["a console hook is set but output has no console"]
+        );
+    }
+
+    #[test]
+    fn test_shared_metadata_is_rendered_after_metadata() {
+        use std::sync::Arc;
+
+        let shared: SharedFields = Arc::from(vec![("region".to_owned(), "eu".to_owned())]);
+        let mut record = LogRecord::new(LogLevel::Info, "hello");
+        record.metadata.push(("user".to_owned(), "bob".to_owned()));
+        record.shared_metadata.push(Arc::clone(&shared));
+        assert_eq!(Logger::format_fields(&record), " user=bob region=eu");
+        assert_eq!(Arc::strong_count(&shared), 2);
+    }
+
+    #[test]
+    fn test_module_level_off() {
+        assert!(LevelFilter::Min(LogLevel::Warning).allows(LogLevel::Error));
+        assert!(!LevelFilter::Min(LogLevel::Warning).allows(LogLevel::Success));
+        assert!(!LevelFilter::Off.allows(LogLevel::Error));
+
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        Logger::set_module_level("chatty_dep::*", LevelFilter::Off);
+        Logger::set_module_level("chatty_dep::important", LevelFilter::Min(LogLevel::Error));
+        let enabled = [
+            Logger::level_enabled(LogLevel::Error, Some("chatty_dep::pool")),
+            Logger::level_enabled(LogLevel::Error, Some("chatty_dep::important")),
+            Logger::level_enabled(LogLevel::Warning, Some("chatty_dep::important")),
+        ];
+        Logger::clear_module_levels();
+
+        assert_eq!(enabled, [false, true, false]);
+    }
+
+    #[test]
+    fn test_disable_callsite() {
+        let line = line!() + 2;
+        let log_here = |n: u32| {
+            crate::log_info!("callsite {n}");
+        };
+        Logger::disable_callsite(file!(), line);
+        let (_, captured) = Logger::capture(|| log_here(1));
+        assert!(captured.is_empty());
+        assert!(Logger::disabled_callsites().contains(&(file!().to_owned(), line)));
+
+        Logger::enable_callsite(file!(), line);
+        let (_, captured) = Logger::capture(|| log_here(2));
+        assert_eq!(captured.len(), 1);
+    }
+
+    #[test]
+    fn test_replay_captured_lines() {
+        use std::sync::Arc;
+
+        let (_, captured) = Logger::capture(|| {
+            crate::log_debug!("replay-context one");
+            crate::log_debug!("replay-context two");
+        });
+
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        Logger::set_output(Output::Console);
+        Logger::set_console_hook(move |line| {
+            if line.contains("replay-context") {
+                sink.lock().unwrap().push(line.to_owned());
+            }
+        });
+        Logger::replay(&captured);
+        Logger::clear_console_hook();
+        Logger::set_output(Output::ConsoleAndFile);
+
+        assert_eq!(*seen.lock().unwrap(), captured);
+    }
+
+    #[test]
+    fn test_replay_routes_by_level() {
+        let warning = Logger::replayed_record("2025-01-01 00:00:00 [WARNING]: x tenant_id=acme");
+        assert_eq!(warning.level, LogLevel::Warning);
+        assert_eq!(warning.tenant_id.as_deref(), Some("acme"));
+        let json = Logger::replayed_record(r#"{"level":"ERROR","message":"[x]"}"#);
+        assert_eq!(json.level, LogLevel::Error);
+        assert_eq!(Logger::replayed_record("no level here").level, LogLevel::Info);
+
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let path = std::env::temp_dir().join(format!("firo-replay-{}.log", std::process::id()));
+        Logger::add_route(Route::to_file(&path).min_level(LogLevel::Warning));
+        Logger::set_file_level(LevelFilter::Off);
+        Logger::set_console_hook(|_| {});
+        Logger::replay(&[
+            "2025-01-01 00:00:00 [DEBUG]: replayed context".to_owned(),
+            "2025-01-01 00:00:01 [WARNING]: replayed failure".to_owned(),
+        ]);
+        Logger::clear_console_hook();
+        Logger::set_file_level(LevelFilter::Min(LogLevel::Debug));
+        Logger::clear_routes();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "2025-01-01 00:00:01 [WARNING]: replayed failure\n");
+    }
+
+    #[test]
+    fn test_console_level() {
+        use std::sync::Arc;
+
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        Logger::set_output(Output::Console);
+        Logger::set_console_level(LevelFilter::Min(LogLevel::Warning));
+        Logger::set_console_hook(move |line| {
+            if line.contains("console-level") {
+                sink.lock().unwrap().push(line.to_owned());
+            }
+        });
+        Logger::log_record(LogRecord::new(LogLevel::Info, "console-level info"));
+        Logger::log_record(LogRecord::new(LogLevel::Warning, "console-level warning"));
+        Logger::clear_console_hook();
+        Logger::set_console_level(LevelFilter::Min(LogLevel::Debug));
+        Logger::set_output(Output::ConsoleAndFile);
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert!(seen[0].contains("console-level warning"));
+    }
+
+    #[test]
+    fn test_remote_preserves_order() {
+        use crate::remote::{serve, RemoteWriter};
+        use std::time::{Duration, Instant, UNIX_EPOCH};
+
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || serve(listener));
+        Logger::start_capture();
+
+        let writer = RemoteWriter::connect(addr).unwrap();
+        for n in 0..20u64 {
+            let mut record = LogRecord::new(LogLevel::Info, format!("ordered-{n}"));
+            record.timestamp = UNIX_EPOCH + Duration::from_nanos(1_000_000_000 + n);
+            writer.send(&record).unwrap();
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut received = Vec::new();
+        while received.len() < 20 && Instant::now() < deadline {
+            received.extend(
+                Logger::take_captured()
+                    .into_iter()
+                    .filter(|line| line.contains("ordered-")),
+            );
+            std::thread::yield_now();
+        }
+        Logger::stop_capture();
+
+        assert_eq!(received.len(), 20);
+        for (n, line) in received.iter().enumerate() {
+            assert!(line.ends_with(&format!(" [INFO]: ordered-{n} ")));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_log_command() {
+        use crate::utils::log_command;
+        use std::process::Command;
+
+        let (status, captured) = Logger::capture(|| {
+            log_command(Command::new("sh").args(["-c", "echo out; echo err >&2; exit 3"]))
+        });
+        assert_eq!(status.unwrap().code(), Some(3));
+        assert!(captured.iter().any(|line| line.contains("[INFO]: out command=sh")));
+        assert!(captured.iter().any(|line| line.contains("[WARNING]: err command=sh")));
+        assert!(captured.last().unwrap().contains("[ERROR]: sh exited with exit status: 3 command=sh"));
+    }
+
+    #[test]
+    fn test_escaped_bytes_never_emit_raw_control_characters() {
+        assert_eq!(
+            EscapedBytes(b"ok\n\x1b[31m\xff\xfe caf\xc3\xa9").to_string(),
+            "ok\\n\\u{1b}[31m\\xff\\xfe café"
+        );
+
+        // Cheap deterministic fuzzing over pseudo-random byte strings.
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..2_000 {
+            let len = (state % 64) as usize;
+            let bytes: Vec<u8> = (0..len)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            let escaped = EscapedBytes(&bytes).to_string();
+            assert!(!escaped.chars().any(char::is_control), "{bytes:?} -> {escaped:?}");
+        }
+
+        let (_, captured) =
+            Logger::capture(|| crate::log_bytes!(LogLevel::Warning, "payload", vec![0x66, 0x0a, 0x80]));
+        assert!(captured[0].contains("[WARNING]: payload: f\\n\\x80 "));
+    }
+
+    #[test]
+    fn test_render_message_is_bounded_and_panic_free() {
+        struct Failing;
+
+        impl std::fmt::Display for Failing {
+            fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                Err(std::fmt::Error)
+            }
+        }
+
+        assert_eq!(Logger::render_message_limited(format_args!("a{}b", Failing), 0), "a");
+
+        let huge = vec![1u32; 1_000_000];
+        let bounded = Logger::render_message_limited(format_args!("{huge:?}"), 10);
+        let multibyte = Logger::render_message_limited(format_args!("ééééééé"), 10);
+        assert_eq!(bounded, "[1, 1, 1, …[truncated]");
+        assert_eq!(multibyte, "ééééé…[truncated]");
+    }
+
+    #[test]
+    fn test_level_streams() {
+        let _guard = GLOBAL_SETTINGS.lock().unwrap();
+        Logger::set_all_streams(Stream::Stderr);
+        Logger::set_level_stream(LogLevel::Info, Stream::Stdout);
+        let audit = Logger::register_level("TEST_STREAM_AUDIT", 3, Colours::CYAN);
+        let info = Logger::stream_for(LogLevel::Info);
+        let error = Logger::stream_for(LogLevel::Error);
+        let custom = Logger::stream_for(audit);
+        Logger::set_all_streams(Stream::Stdout);
+
+        assert_eq!(info, Stream::Stdout);
+        assert_eq!(error, Stream::Stderr);
+        assert_eq!(custom, Stream::Stderr);
+    }
+
+    #[test]
+    fn test_shorten_module() {
+        let module = "app::db::pool::handler";
+        assert_eq!(shorten_module(module, ModuleStyle::Hidden, false), None);
+        assert_eq!(shorten_module(module, ModuleStyle::Full, false).unwrap(), module);
+        assert_eq!(
+            shorten_module(module, ModuleStyle::Dotted, false).unwrap(),
+            "app.db.pool::handler"
+        );
+        assert_eq!(
+            shorten_module(module, ModuleStyle::LastSegments(2), false).unwrap(),
+            "pool::handler"
+        );
+        assert_eq!(
+            shorten_module(module, ModuleStyle::Full, true).unwrap(),
+            "db::pool::handler"
+        );
+        assert_eq!(shorten_module("app", ModuleStyle::Dotted, true).unwrap(), "app");
+    }
+
+    #[test]
+    fn test_trim_source_path() {
+        let file = "/home/ci/build/app/src/handlers/user.rs";
+        assert_eq!(trim_source_path(file, "/home/ci/build/app"), "src/handlers/user.rs");
+        assert_eq!(trim_source_path(file, "/home/ci/build/app/"), "src/handlers/user.rs");
+        assert_eq!(trim_source_path(file, "/home/ci/build/ap"), file);
+        assert_eq!(trim_source_path(file, "/srv"), file);
+        assert_eq!(trim_source_path("src/lib.rs", ""), "src/lib.rs");
     }
 }